@@ -0,0 +1,322 @@
+//! Pre/post account state of a simulation, decoded with the crate's own layouts
+
+use std::mem::size_of;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+use crate::raydium::client::RAYDIUM_AMM_PROGRAM_ID;
+use crate::raydium::state::AmmInfo;
+use crate::solfi::client::SOLFI_PROGRAM_ID;
+use crate::solfi::MarketAccount;
+
+/// Raydium amm reserves and swap counters, u128 counters as strings
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AmmView {
+    pub status: u64,
+    pub state: u64,
+    pub coin_vault: String,
+    pub pc_vault: String,
+    pub coin_vault_mint: String,
+    pub pc_vault_mint: String,
+    pub lp_amount: u64,
+    pub need_take_pnl_coin: u64,
+    pub need_take_pnl_pc: u64,
+    pub total_pnl_coin: u64,
+    pub total_pnl_pc: u64,
+    pub swap_coin_in_amount: String,
+    pub swap_pc_out_amount: String,
+    pub swap_acc_pc_fee: u64,
+    pub swap_pc_in_amount: String,
+    pub swap_coin_out_amount: String,
+    pub swap_acc_coin_fee: u64,
+}
+
+impl AmmView {
+    pub fn new(amm: &AmmInfo) -> Self {
+        AmmView {
+            status: amm.status,
+            state: amm.state,
+            coin_vault: amm.coin_vault.to_string(),
+            pc_vault: amm.pc_vault.to_string(),
+            coin_vault_mint: amm.coin_vault_mint.to_string(),
+            pc_vault_mint: amm.pc_vault_mint.to_string(),
+            lp_amount: amm.lp_amount,
+            need_take_pnl_coin: amm.state_data.need_take_pnl_coin,
+            need_take_pnl_pc: amm.state_data.need_take_pnl_pc,
+            total_pnl_coin: amm.state_data.total_pnl_coin,
+            total_pnl_pc: amm.state_data.total_pnl_pc,
+            swap_coin_in_amount: amm.state_data.swap_coin_in_amount.to_string(),
+            swap_pc_out_amount: amm.state_data.swap_pc_out_amount.to_string(),
+            swap_acc_pc_fee: amm.state_data.swap_acc_pc_fee,
+            swap_pc_in_amount: amm.state_data.swap_pc_in_amount.to_string(),
+            swap_coin_out_amount: amm.state_data.swap_coin_out_amount.to_string(),
+            swap_acc_coin_fee: amm.state_data.swap_acc_coin_fee,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenAccountView {
+    pub mint: String,
+    pub owner: String,
+    pub amount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SolfiMarketView {
+    pub enabled: u8,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_vault: String,
+    pub quote_vault: String,
+    pub price_decimals: i64,
+    pub price_quote_atoms_per_base_atom: u64,
+    pub price_updated_slot: u64,
+    pub price_last_valid_slot: u64,
+    pub volatility_milli_scale: u64,
+    pub retreat_milli_bips: u64,
+    pub retreat_quote_amount: u64,
+}
+
+impl SolfiMarketView {
+    pub fn new(market: &MarketAccount) -> Self {
+        SolfiMarketView {
+            enabled: market.market_config.enabled,
+            base_mint: market.base_mint.to_string(),
+            quote_mint: market.quote_mint.to_string(),
+            base_vault: market.base_vault.to_string(),
+            quote_vault: market.quote_vault.to_string(),
+            price_decimals: market.market_price.price_decimals,
+            price_quote_atoms_per_base_atom: market.market_price.price_quote_atoms_per_base_atom,
+            price_updated_slot: market.market_price.price_updated_slot,
+            price_last_valid_slot: market.market_price.price_last_valid_slot,
+            volatility_milli_scale: market.market_price.volatility_milli_scale,
+            retreat_milli_bips: market.market_config.retreat_milli_bips,
+            retreat_quote_amount: market.market_config.retreat_quote_amount,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DecodedAccount {
+    RaydiumAmm(AmmView),
+    TokenAccount(TokenAccountView),
+    SolfiMarket(SolfiMarketView),
+    /// any account none of the layouts above match
+    Other { owner: String, data_len: usize },
+}
+
+impl DecodedAccount {
+    /// Picks the layout from the owner and data length
+    pub fn decode(account: &Account) -> Self {
+        if account.owner == spl_token::id() && account.data.len() == spl_token::state::Account::LEN {
+            if let Ok(token) = spl_token::state::Account::unpack(&account.data) {
+                return DecodedAccount::TokenAccount(TokenAccountView {
+                    mint: token.mint.to_string(),
+                    owner: token.owner.to_string(),
+                    amount: token.amount,
+                });
+            }
+        }
+        if account.owner == Pubkey::from_str(SOLFI_PROGRAM_ID).unwrap()
+            && account.data.len() == size_of::<MarketAccount>()
+        {
+            if let Ok(market) = bytemuck::try_pod_read_unaligned::<MarketAccount>(&account.data) {
+                return DecodedAccount::SolfiMarket(SolfiMarketView::new(&market));
+            }
+        }
+        if account.owner == Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap()
+            && account.data.len() == size_of::<AmmInfo>()
+        {
+            // account data is not guaranteed to be aligned for AmmInfo, read a copy
+            if let Ok(amm) = bytemuck::try_pod_read_unaligned::<AmmInfo>(&account.data) {
+                return DecodedAccount::RaydiumAmm(AmmView::new(&amm));
+            }
+        }
+        DecodedAccount::Other {
+            owner: account.owner.to_string(),
+            data_len: account.data.len(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub pre: Value,
+    pub post: Value,
+}
+
+/// One account before and after a simulation, `None` when the account does not exist
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    pub pubkey: String,
+    pub pre_lamports: u64,
+    pub post_lamports: u64,
+    pub pre: Option<DecodedAccount>,
+    pub post: Option<DecodedAccount>,
+    /// decoded fields whose value changed, empty when the layout changed
+    pub changes: Vec<FieldChange>,
+}
+
+impl AccountDiff {
+    pub fn new(pubkey: &Pubkey, pre: Option<&Account>, post: Option<&Account>) -> Self {
+        let pre_decoded = pre.map(DecodedAccount::decode);
+        let post_decoded = post.map(DecodedAccount::decode);
+        let changes = match (&pre_decoded, &post_decoded) {
+            (Some(pre), Some(post)) => field_changes(pre, post),
+            _ => vec![],
+        };
+        AccountDiff {
+            pubkey: pubkey.to_string(),
+            pre_lamports: pre.map(|account| account.lamports).unwrap_or_default(),
+            post_lamports: post.map(|account| account.lamports).unwrap_or_default(),
+            pre: pre_decoded,
+            post: post_decoded,
+            changes,
+        }
+    }
+}
+
+fn field_changes(pre: &DecodedAccount, post: &DecodedAccount) -> Vec<FieldChange> {
+    let (pre, post) = match (serde_json::to_value(pre), serde_json::to_value(post)) {
+        (Ok(Value::Object(pre)), Ok(Value::Object(post))) => (pre, post),
+        _ => return vec![],
+    };
+    if pre.get("type") != post.get("type") {
+        return vec![];
+    }
+    pre.into_iter()
+        .filter_map(|(field, pre_value)| {
+            let post_value = post.get(&field).cloned().unwrap_or(Value::Null);
+            if pre_value == post_value {
+                None
+            } else {
+                Some(FieldChange {
+                    field,
+                    pre: pre_value,
+                    post: post_value,
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+    use spl_token::state::AccountState;
+
+    fn account(owner: &Pubkey, data: Vec<u8>) -> Account {
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner: *owner,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+        let token = spl_token::state::Account {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: AccountState::Initialized,
+            ..Default::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(token, &mut data).unwrap();
+        account(&spl_token::id(), data)
+    }
+
+    fn amm_data() -> Vec<u8> {
+        let mut amm = AmmInfo::default();
+        amm.status = 6;
+        amm.coin_vault_mint = Pubkey::new_unique();
+        amm.pc_vault_mint = Pubkey::new_unique();
+        amm.lp_amount = 1_000;
+        amm.state_data.swap_coin_in_amount = u128::MAX;
+        bytemuck::bytes_of(&amm).to_vec()
+    }
+
+    #[test]
+    fn decodes_token_account() {
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        assert_eq!(
+            DecodedAccount::decode(&token_account(&mint, &owner, 42)),
+            DecodedAccount::TokenAccount(TokenAccountView {
+                mint: mint.to_string(),
+                owner: owner.to_string(),
+                amount: 42,
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_solfi_market() {
+        let mut market = MarketAccount::zeroed();
+        market.base_mint = Pubkey::new_unique();
+        market.market_price.price_quote_atoms_per_base_atom = 150;
+        let solfi = Pubkey::from_str(SOLFI_PROGRAM_ID).unwrap();
+        match DecodedAccount::decode(&account(&solfi, bytemuck::bytes_of(&market).to_vec())) {
+            DecodedAccount::SolfiMarket(view) => {
+                assert_eq!(view, SolfiMarketView::new(&market));
+                assert_eq!(view.base_mint, market.base_mint.to_string());
+                assert_eq!(view.price_quote_atoms_per_base_atom, 150);
+            }
+            other => panic!("decoded as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_raydium_amm_only_under_the_amm_program() {
+        let raydium = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+        match DecodedAccount::decode(&account(&raydium, amm_data())) {
+            DecodedAccount::RaydiumAmm(view) => {
+                assert_eq!(view.status, 6);
+                assert_eq!(view.lp_amount, 1_000);
+                assert_eq!(view.swap_coin_in_amount, u128::MAX.to_string());
+            }
+            other => panic!("decoded as {:?}", other),
+        }
+
+        let other_owner = Pubkey::new_unique();
+        assert_eq!(
+            DecodedAccount::decode(&account(&other_owner, amm_data())),
+            DecodedAccount::Other {
+                owner: other_owner.to_string(),
+                data_len: size_of::<AmmInfo>(),
+            }
+        );
+    }
+
+    #[test]
+    fn diff_lists_changed_fields() {
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let pubkey = Pubkey::new_unique();
+        let pre = token_account(&mint, &owner, 42);
+        let post = token_account(&mint, &owner, 40);
+        let diff = AccountDiff::new(&pubkey, Some(&pre), Some(&post));
+        assert_eq!(
+            diff.changes,
+            vec![FieldChange {
+                field: "amount".to_string(),
+                pre: Value::from(42),
+                post: Value::from(40),
+            }]
+        );
+
+        // an account that appears has no field changes
+        let created = AccountDiff::new(&pubkey, None, Some(&post));
+        assert_eq!(created.pre, None);
+        assert!(created.changes.is_empty());
+    }
+}
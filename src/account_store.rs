@@ -0,0 +1,247 @@
+//! In-memory accounts that instructions can be executed against locally
+
+use std::collections::HashMap;
+use solana_client::client_error::ClientError;
+use solana_client::rpc_client::RpcClient;
+use solana_program::account_info::AccountInfo;
+use solana_program::entrypoint::ProgramResult;
+use solana_program::instruction::Instruction;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+use crate::raydium::processor::Processor;
+
+/// Most keys `get_multiple_accounts` accepts in one request
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+#[derive(Clone, Debug, Default)]
+pub struct AccountStore {
+    accounts: HashMap<Pubkey, Account>,
+}
+
+impl AccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_accounts(accounts: impl IntoIterator<Item = (Pubkey, Account)>) -> Self {
+        AccountStore {
+            accounts: accounts.into_iter().collect(),
+        }
+    }
+
+    /// Loads `keys` from the cluster, keys without an account are left out
+    pub fn fetch(&mut self, client: &RpcClient, keys: &[Pubkey]) -> Result<(), ClientError> {
+        self.fetch_with(keys, |chunk| client.get_multiple_accounts(chunk))
+    }
+
+    /// `fetch` with `get_multiple_accounts` called once per chunk of at most `MAX_MULTIPLE_ACCOUNTS` keys
+    fn fetch_with<E>(
+        &mut self,
+        keys: &[Pubkey],
+        mut get_multiple_accounts: impl FnMut(&[Pubkey]) -> Result<Vec<Option<Account>>, E>,
+    ) -> Result<(), E> {
+        for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = get_multiple_accounts(chunk)?;
+            for (key, account) in chunk.iter().zip(accounts) {
+                if let Some(account) = account {
+                    self.accounts.insert(*key, account);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, key: Pubkey, account: Account) -> Option<Account> {
+        self.accounts.insert(key, account)
+    }
+
+    pub fn get(&self, key: &Pubkey) -> Option<&Account> {
+        self.accounts.get(key)
+    }
+
+    pub fn remove(&mut self, key: &Pubkey) -> Option<Account> {
+        self.accounts.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Pubkey, &Account)> {
+        self.accounts.iter()
+    }
+
+    /// Runs `process` over the accounts of `instruction` the way the runtime lays them out.
+    /// A key listed more than once shares one account with the union of its signer/writable
+    /// flags, and keys missing from the store start as empty system accounts.
+    /// Writable accounts are written back only when `process` succeeds.
+    pub fn execute<F>(&mut self, instruction: &Instruction, process: F) -> ProgramResult
+    where
+        F: FnOnce(&Pubkey, &[AccountInfo], &[u8]) -> ProgramResult,
+    {
+        let mut keys: Vec<Pubkey> = Vec::new();
+        let mut flags: Vec<(bool, bool)> = Vec::new();
+        for meta in instruction.accounts.iter() {
+            match keys.iter().position(|key| *key == meta.pubkey) {
+                Some(index) => {
+                    flags[index].0 |= meta.is_signer;
+                    flags[index].1 |= meta.is_writable;
+                }
+                None => {
+                    keys.push(meta.pubkey);
+                    flags.push((meta.is_signer, meta.is_writable));
+                }
+            }
+        }
+        let mut accounts: Vec<Account> = keys
+            .iter()
+            .map(|key| self.accounts.get(key).cloned().unwrap_or_default())
+            .collect();
+
+        {
+            let infos: Vec<AccountInfo> = keys
+                .iter()
+                .zip(accounts.iter_mut())
+                .zip(flags.iter())
+                .map(|((key, account), (is_signer, is_writable))| {
+                    AccountInfo::new(
+                        key,
+                        *is_signer,
+                        *is_writable,
+                        &mut account.lamports,
+                        &mut account.data,
+                        &account.owner,
+                        account.executable,
+                        account.rent_epoch,
+                    )
+                })
+                .collect();
+            let account_infos: Vec<AccountInfo> = instruction
+                .accounts
+                .iter()
+                .map(|meta| {
+                    let index = keys.iter().position(|key| *key == meta.pubkey).unwrap();
+                    infos[index].clone()
+                })
+                .collect();
+            process(&instruction.program_id, &account_infos, &instruction.data)?;
+        }
+
+        for ((key, account), (_, is_writable)) in keys.into_iter().zip(accounts).zip(flags) {
+            if is_writable && (self.accounts.contains_key(&key) || account != Account::default())
+            {
+                self.accounts.insert(key, account);
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes an instruction built by `raydium::instruction`
+    pub fn execute_raydium(&mut self, instruction: &Instruction) -> ProgramResult {
+        self.execute(instruction, Processor::process)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use solana_program::instruction::AccountMeta;
+    use solana_program::program_error::ProgramError;
+
+    fn account(lamports: u64, data: &[u8]) -> Account {
+        Account {
+            lamports,
+            data: data.to_vec(),
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn instruction(accounts: Vec<AccountMeta>) -> Instruction {
+        Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts,
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn duplicate_keys_share_one_account() {
+        let key = Pubkey::new_unique();
+        let mut store = AccountStore::from_accounts(vec![(key, account(10, &[0; 4]))]);
+        let ix = instruction(vec![AccountMeta::new(key, false), AccountMeta::new_readonly(key, true)]);
+        store
+            .execute(&ix, |_, accounts, _| {
+                assert_eq!(accounts.len(), 2);
+                // the flags of both metas apply to both infos
+                assert!(accounts[0].is_signer && accounts[0].is_writable);
+                assert!(accounts[1].is_signer && accounts[1].is_writable);
+                assert!(Rc::ptr_eq(&accounts[0].data, &accounts[1].data));
+                accounts[1].data.borrow_mut()[0] = 7;
+                **accounts[1].lamports.borrow_mut() = 11;
+                assert_eq!(accounts[0].data.borrow()[0], 7);
+                assert_eq!(accounts[0].lamports(), 11);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(store.get(&key).unwrap().data, vec![7, 0, 0, 0]);
+        assert_eq!(store.get(&key).unwrap().lamports, 11);
+    }
+
+    #[test]
+    fn writes_back_writable_accounts_only_on_success() {
+        let writable = Pubkey::new_unique();
+        let readonly = Pubkey::new_unique();
+        let mut store = AccountStore::from_accounts(vec![
+            (writable, account(10, &[0; 4])),
+            (readonly, account(10, &[0; 4])),
+        ]);
+        let ix = instruction(vec![AccountMeta::new(writable, false), AccountMeta::new_readonly(readonly, false)]);
+        fn mutate(accounts: &[AccountInfo]) {
+            for account in accounts {
+                account.data.borrow_mut()[0] = 1;
+                **account.lamports.borrow_mut() = 20;
+            }
+        }
+
+        let result = store.execute(&ix, |_, accounts, _| {
+            mutate(accounts);
+            Err(ProgramError::Custom(1))
+        });
+        assert_eq!(result, Err(ProgramError::Custom(1)));
+        assert_eq!(store.get(&writable).unwrap().data, vec![0; 4]);
+        assert_eq!(store.get(&writable).unwrap().lamports, 10);
+
+        store
+            .execute(&ix, |_, accounts, _| {
+                mutate(accounts);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(store.get(&writable).unwrap().data, vec![1, 0, 0, 0]);
+        assert_eq!(store.get(&writable).unwrap().lamports, 20);
+        assert_eq!(store.get(&readonly).unwrap().data, vec![0; 4]);
+        assert_eq!(store.get(&readonly).unwrap().lamports, 10);
+    }
+
+    #[test]
+    fn fetch_requests_chunks_and_skips_missing_accounts() {
+        let keys: Vec<Pubkey> = (0..MAX_MULTIPLE_ACCOUNTS + 50).map(|_| Pubkey::new_unique()).collect();
+        let mut chunk_lens = Vec::new();
+        let mut store = AccountStore::new();
+        store
+            .fetch_with(&keys, |chunk| {
+                chunk_lens.push(chunk.len());
+                // every other key has no account
+                let accounts = chunk
+                    .iter()
+                    .map(|key| keys.iter().position(|k| k == key).filter(|i| i % 2 == 0).map(|_| account(1, &[])))
+                    .collect();
+                Ok::<_, ClientError>(accounts)
+            })
+            .unwrap();
+        assert_eq!(chunk_lens, vec![MAX_MULTIPLE_ACCOUNTS, 50]);
+        assert_eq!(store.iter().count(), (MAX_MULTIPLE_ACCOUNTS + 50) / 2);
+        assert!(store.get(&keys[0]).is_some());
+        assert!(store.get(&keys[1]).is_none());
+        assert!(store.get(&keys[MAX_MULTIPLE_ACCOUNTS + 48]).is_some());
+    }
+}
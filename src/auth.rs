@@ -0,0 +1,463 @@
+//! Api key and HMAC request authentication with per-key permissions.
+//!
+//! A client either sends its secret in `X-Api-Key`, or signs the request and sends
+//! `X-Api-Key-Id` (key name), `X-Timestamp` (unix seconds), `X-Nonce` (8 to 64 characters,
+//! never reused by the key), `X-Content-Sha256` (hex sha256 of the body, of the empty string
+//! for requests without one) and `X-Signature`: hex HMAC-SHA256 of
+//! `"{timestamp}\n{nonce}\n{method}\n{uri}\n{content_sha256}"` with the secret.
+//! A nonce is remembered until its timestamp leaves the allowed skew, so a captured request
+//! can't be replayed. Signed bodies are read through `SignedJson`, which checks them against
+//! `X-Content-Sha256`.
+//! With no keys configured only loopback clients are let in, with every permission, and the
+//! server refuses to start on a non-loopback address.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use rocket::data::{self, Data, FromDataSimple};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::config::ConfigError;
+use rocket::{Outcome, State};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use crate::error::Error;
+
+/// Largest body `SignedJson` reads
+const BODY_LIMIT: u64 = 1 << 20;
+const DEFAULT_MAX_SKEW_SECS: u64 = 300;
+const MIN_NONCE_LEN: usize = 8;
+const MAX_NONCE_LEN: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Permission {
+    /// rpc reads and simulations
+    Read,
+    /// submitting client signed transactions
+    Submit,
+    /// anything signed with a server key
+    Sign,
+}
+
+impl Permission {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "read" => Ok(Permission::Read),
+            "submit" => Ok(Permission::Submit),
+            "sign" => Ok(Permission::Sign),
+            _ => Err(anyhow!("unknown permission {}", value)),
+        }
+    }
+}
+
+struct ApiKey {
+    secret: Vec<u8>,
+    permissions: HashSet<Permission>,
+    /// the secret may not be sent as `X-Api-Key`, requests must be signed
+    hmac_only: bool,
+}
+
+/// Api keys by name, from the `api_keys` table of Rocket.toml:
+///
+/// ```toml
+/// [global.api_keys.monitor]
+/// key_env = "MONITOR_API_KEY"
+/// permissions = ["read"]
+/// [global.api_keys.bot]
+/// key_env = "BOT_API_KEY"
+/// permissions = ["read", "submit", "sign"]
+/// hmac_only = true
+/// ```
+pub struct ApiKeys {
+    keys: HashMap<String, ApiKey>,
+    max_skew_secs: u64,
+    /// nonces of verified signed requests by key name, with the unix second they expire at
+    seen_nonces: Mutex<HashMap<(String, String), u64>>,
+}
+
+fn is_loopback_address(address: &str) -> bool {
+    address == "localhost" || address.parse::<IpAddr>().map_or(false, |ip| ip.is_loopback())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+impl ApiKeys {
+    pub fn from_config(config: &rocket::Config) -> Result<Self> {
+        let max_skew_secs = match config.get_int("auth_max_skew_secs") {
+            Ok(secs) if secs > 0 => secs as u64,
+            Ok(secs) => return Err(anyhow!("invalid auth_max_skew_secs: {}", secs)),
+            Err(ConfigError::Missing(_)) => DEFAULT_MAX_SKEW_SECS,
+            Err(e) => return Err(anyhow!("invalid auth_max_skew_secs: {}", e)),
+        };
+        let mut keys = HashMap::new();
+        let table = match config.get_table("api_keys") {
+            Ok(table) => Some(table),
+            Err(ConfigError::Missing(_)) => None,
+            Err(e) => return Err(anyhow!("invalid api_keys: {}", e)),
+        };
+        if let Some(table) = table {
+            for (name, entry) in table.iter() {
+                let secret = match (
+                    entry.get("key").and_then(|value| value.as_str()),
+                    entry.get("key_env").and_then(|value| value.as_str()),
+                ) {
+                    (Some(key), _) => key.to_string(),
+                    (None, Some(var)) => env::var(var)
+                        .map_err(|_| anyhow!("api key {}: {} is not set", name, var))?,
+                    (None, None) => return Err(anyhow!("api key {} needs key or key_env", name)),
+                };
+                if secret.len() < 16 {
+                    return Err(anyhow!("api key {} is shorter than 16 characters", name));
+                }
+                let permissions = entry
+                    .get("permissions")
+                    .and_then(|value| value.as_array())
+                    .ok_or_else(|| anyhow!("api key {} needs permissions", name))?
+                    .iter()
+                    .map(|value| {
+                        value
+                            .as_str()
+                            .ok_or_else(|| anyhow!("api key {} permissions must be strings", name))
+                            .and_then(Permission::parse)
+                    })
+                    .collect::<Result<HashSet<Permission>>>()?;
+                let hmac_only = entry.get("hmac_only").and_then(|value| value.as_bool()).unwrap_or(false);
+                keys.insert(
+                    name.clone(),
+                    ApiKey {
+                        secret: secret.into_bytes(),
+                        permissions,
+                        hmac_only,
+                    },
+                );
+            }
+        }
+        if keys.is_empty() && !is_loopback_address(&config.address) {
+            return Err(anyhow!(
+                "no api_keys configured, refusing to serve non-loopback address {}",
+                config.address
+            ));
+        }
+        Ok(ApiKeys {
+            keys,
+            max_skew_secs,
+            seen_nonces: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn by_secret(&self, secret: &str) -> Option<(&String, &ApiKey)> {
+        // compare against every key so the time taken doesn't tell which one matched
+        let mut found = None;
+        for (name, key) in self.keys.iter() {
+            if bool::from(key.secret.ct_eq(secret.as_bytes())) {
+                found = Some((name, key));
+            }
+        }
+        found
+    }
+
+    fn verify_signature(&self, request: &Request, name: &str) -> std::result::Result<&ApiKey, Error> {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get_one(name)
+                .ok_or_else(|| Error::Unauthorized(format!("missing {}", name)))
+        };
+        let uri = request.uri().to_string();
+        let signed = SignedRequest {
+            timestamp: header("X-Timestamp")?,
+            nonce: header("X-Nonce")?,
+            method: request.method().as_str(),
+            uri: &uri,
+            content_sha256: header("X-Content-Sha256")?,
+            signature: header("X-Signature")?,
+        };
+        self.check_signature(name, &signed, unix_now())
+    }
+
+    /// Verifies `signed` against the secret of key `name` and records its nonce
+    fn check_signature(&self, name: &str, signed: &SignedRequest, now: u64) -> std::result::Result<&ApiKey, Error> {
+        let key = self
+            .keys
+            .get(name)
+            .ok_or_else(|| Error::Unauthorized("unknown api key".to_string()))?;
+        let signature = hex::decode(signed.signature)
+            .map_err(|_| Error::Unauthorized("X-Signature is not hex".to_string()))?;
+        if !(MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&signed.nonce.len()) {
+            return Err(Error::Unauthorized(format!(
+                "X-Nonce must be {} to {} characters",
+                MIN_NONCE_LEN, MAX_NONCE_LEN
+            )));
+        }
+
+        let sent_at: u64 = signed
+            .timestamp
+            .parse()
+            .map_err(|_| Error::Unauthorized("X-Timestamp is not unix seconds".to_string()))?;
+        if now.abs_diff(sent_at) > self.max_skew_secs {
+            return Err(Error::Unauthorized("X-Timestamp is outside the allowed skew".to_string()));
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key.secret)
+            .map_err(|_| Error::Unauthorized("invalid api key".to_string()))?;
+        mac.update(signed.message().as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| Error::Unauthorized("bad signature".to_string()))?;
+
+        // only verified nonces are kept, and only while their timestamp is accepted
+        let mut seen_nonces = self.seen_nonces.lock().unwrap();
+        seen_nonces.retain(|_, expires_at| *expires_at >= now);
+        let expires_at = sent_at.saturating_add(self.max_skew_secs);
+        if seen_nonces.insert((name.to_string(), signed.nonce.to_string()), expires_at).is_some() {
+            return Err(Error::Unauthorized("X-Nonce was already used".to_string()));
+        }
+        Ok(key)
+    }
+
+    /// Checks the request credentials and that they grant `permission`
+    pub fn authorize(&self, request: &Request, permission: Permission) -> std::result::Result<(), Error> {
+        if self.keys.is_empty() {
+            // the peer address, X-Real-IP is set by the client and can't be trusted here
+            return match request.remote() {
+                Some(addr) if addr.ip().is_loopback() => Ok(()),
+                _ => Err(Error::Unauthorized("no api keys configured, only local clients are served".to_string())),
+            };
+        }
+
+        let key = if let Some(name) = request.headers().get_one("X-Api-Key-Id") {
+            let key = self.verify_signature(request, name)?;
+            let content_sha256 = request.headers().get_one("X-Content-Sha256").unwrap_or_default();
+            request.local_cache(|| SignedContent(Some(content_sha256.to_lowercase())));
+            key
+        } else if let Some(secret) = request.headers().get_one("X-Api-Key") {
+            let (_, key) = self
+                .by_secret(secret)
+                .ok_or_else(|| Error::Unauthorized("unknown api key".to_string()))?;
+            if key.hmac_only {
+                return Err(Error::Unauthorized("api key must sign its requests".to_string()));
+            }
+            key
+        } else {
+            return Err(Error::Unauthorized("missing X-Api-Key or X-Api-Key-Id".to_string()));
+        };
+
+        if key.permissions.contains(&permission) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden(format!("api key lacks {:?} permission", permission)))
+        }
+    }
+}
+
+/// Headers of a signed request
+struct SignedRequest<'a> {
+    timestamp: &'a str,
+    nonce: &'a str,
+    method: &'a str,
+    uri: &'a str,
+    content_sha256: &'a str,
+    /// hex HMAC-SHA256 of `message()`
+    signature: &'a str,
+}
+
+impl SignedRequest<'_> {
+    fn message(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.timestamp, self.nonce, self.method, self.uri, self.content_sha256
+        )
+    }
+}
+
+/// Body hash a signed request committed to, cached on the request by `authorize`
+struct SignedContent(Option<String>);
+
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct ReadAccess;
+pub struct SubmitAccess;
+pub struct SignAccess;
+
+impl RequiredPermission for ReadAccess {
+    const PERMISSION: Permission = Permission::Read;
+}
+
+impl RequiredPermission for SubmitAccess {
+    const PERMISSION: Permission = Permission::Submit;
+}
+
+impl RequiredPermission for SignAccess {
+    const PERMISSION: Permission = Permission::Sign;
+}
+
+/// Request guard that lets the request through only if its credentials grant `P`
+pub struct Auth<P: RequiredPermission>(PhantomData<P>);
+
+impl<'a, 'r, P: RequiredPermission> FromRequest<'a, 'r> for Auth<P> {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let keys = match request.guard::<State<ApiKeys>>() {
+            Outcome::Success(keys) => keys,
+            _ => return Outcome::Failure((Status::InternalServerError, Error::Other("api keys not managed".to_string()))),
+        };
+        match keys.authorize(request, P::PERMISSION) {
+            Ok(()) => Outcome::Success(Auth(PhantomData)),
+            Err(e) => Outcome::Failure((e.status(), e)),
+        }
+    }
+}
+
+/// Json body that, on a signed request, must hash to the signed `X-Content-Sha256`.
+/// Put it after an `Auth` guard.
+pub struct SignedJson<T>(pub T);
+
+impl<T> std::ops::Deref for SignedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromDataSimple for SignedJson<T> {
+    type Error = Error;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        let mut body = Vec::new();
+        if let Err(e) = data.open().take(BODY_LIMIT).read_to_end(&mut body) {
+            return Outcome::Failure((Status::BadRequest, Error::Decode(e.to_string())));
+        }
+        if let Some(expected) = &request.local_cache(|| SignedContent(None)).0 {
+            if hex::encode(Sha256::digest(&body)) != *expected {
+                let e = Error::Unauthorized("body does not match X-Content-Sha256".to_string());
+                return Outcome::Failure((e.status(), e));
+            }
+        }
+        match serde_json::from_slice(&body) {
+            Ok(value) => Outcome::Success(SignedJson(value)),
+            Err(e) => Outcome::Failure((Status::BadRequest, Error::Decode(e.to_string()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::config::Environment;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123";
+    const NOW: u64 = 1_700_000_000;
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn api_keys() -> ApiKeys {
+        let mut keys = HashMap::new();
+        keys.insert(
+            "bot".to_string(),
+            ApiKey {
+                secret: SECRET.to_vec(),
+                permissions: [Permission::Read].into_iter().collect(),
+                hmac_only: true,
+            },
+        );
+        ApiKeys {
+            keys,
+            max_skew_secs: DEFAULT_MAX_SKEW_SECS,
+            seen_nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn sign(timestamp: &str, nonce: &str, uri: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET).unwrap();
+        mac.update(format!("{}\n{}\nGET\n{}\n{}", timestamp, nonce, uri, EMPTY_SHA256).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn request<'a>(timestamp: &'a str, nonce: &'a str, uri: &'a str, signature: &'a str) -> SignedRequest<'a> {
+        SignedRequest {
+            timestamp,
+            nonce,
+            method: "GET",
+            uri,
+            content_sha256: EMPTY_SHA256,
+            signature,
+        }
+    }
+
+    #[test]
+    fn accepts_a_signature_once() {
+        let keys = api_keys();
+        let timestamp = NOW.to_string();
+        let signature = sign(&timestamp, "nonce-0001", "/slot");
+        let signed = request(&timestamp, "nonce-0001", "/slot", &signature);
+
+        assert!(keys.check_signature("bot", &signed, NOW).is_ok());
+        assert!(keys.check_signature("bot", &signed, NOW + 1).is_err());
+        // the nonce is forgotten once its timestamp can't be accepted anymore
+        let later = (NOW + 2 * DEFAULT_MAX_SKEW_SECS).to_string();
+        let signature = sign(&later, "nonce-0001", "/slot");
+        let signed = request(&later, "nonce-0001", "/slot", &signature);
+        assert!(keys.check_signature("bot", &signed, NOW + 2 * DEFAULT_MAX_SKEW_SECS).is_ok());
+    }
+
+    #[test]
+    fn rejects_tampered_stale_and_unknown_requests() {
+        let keys = api_keys();
+        let timestamp = NOW.to_string();
+        let signature = sign(&timestamp, "nonce-0002", "/slot");
+
+        let tampered = request(&timestamp, "nonce-0002", "/blockhash", &signature);
+        assert!(keys.check_signature("bot", &tampered, NOW).is_err());
+
+        let signed = request(&timestamp, "nonce-0002", "/slot", &signature);
+        assert!(keys.check_signature("bot", &signed, NOW + DEFAULT_MAX_SKEW_SECS + 1).is_err());
+        assert!(keys.check_signature("monitor", &signed, NOW).is_err());
+
+        let signature = sign(&timestamp, "short", "/slot");
+        let short_nonce = request(&timestamp, "short", "/slot", &signature);
+        assert!(keys.check_signature("bot", &short_nonce, NOW).is_err());
+
+        // none of the rejected requests used up the nonce
+        assert!(keys.check_signature("bot", &signed, NOW).is_ok());
+    }
+
+    #[test]
+    fn no_keys_only_binds_loopback() {
+        let config = |address: &str| {
+            rocket::Config::build(Environment::Development)
+                .address(address)
+                .finalize()
+                .unwrap()
+        };
+        assert!(ApiKeys::from_config(&config("127.0.0.1")).is_ok());
+        assert!(ApiKeys::from_config(&config("localhost")).is_ok());
+        assert!(ApiKeys::from_config(&config("0.0.0.0")).is_err());
+    }
+
+    #[test]
+    fn mistyped_settings_are_errors() {
+        let config = |name: &str, value: &str| {
+            rocket::Config::build(Environment::Development)
+                .address("127.0.0.1")
+                .extra(name, value)
+                .finalize()
+                .unwrap()
+        };
+        assert!(ApiKeys::from_config(&config("api_keys", "monitor")).is_err());
+        assert!(ApiKeys::from_config(&config("auth_max_skew_secs", "300")).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+//! Tracks submitted signatures through processed, confirmed and finalized, or to expiry
+//! when their blockhash is no longer valid and the cluster never saw them.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::TransactionError;
+use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
+use crate::error::{Error, Result};
+
+/// Most signatures `getSignatureStatuses` accepts in one request
+const MAX_SIGNATURE_STATUSES: usize = 256;
+/// How long settled signatures stay queryable
+const RETENTION: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxState {
+    /// not seen by the cluster yet
+    Pending,
+    Processed,
+    Confirmed,
+    Finalized,
+    /// the blockhash expired before the cluster saw the transaction
+    Expired,
+}
+
+impl TxState {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "processed" => Ok(TxState::Processed),
+            "confirmed" => Ok(TxState::Confirmed),
+            "finalized" => Ok(TxState::Finalized),
+            _ => Err(Error::Decode(format!("unknown commitment {}", value))),
+        }
+    }
+
+    /// No later poll can change the state
+    pub fn is_settled(&self) -> bool {
+        matches!(self, TxState::Finalized | TxState::Expired)
+    }
+
+    /// At or past `target`, an expired transaction never reaches one
+    pub fn reached(&self, target: TxState) -> bool {
+        *self != TxState::Expired && *self >= target
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrackedTx {
+    pub signature: String,
+    pub state: TxState,
+    pub slot: Option<u64>,
+    /// set when the transaction landed but failed
+    pub err: Option<TransactionError>,
+    #[serde(skip)]
+    recent_blockhash: Option<Hash>,
+    #[serde(skip)]
+    settled_at: Option<Instant>,
+}
+
+impl TrackedTx {
+    fn new(signature: Signature, recent_blockhash: Option<Hash>) -> Self {
+        TrackedTx {
+            signature: signature.to_string(),
+            state: TxState::Pending,
+            slot: None,
+            err: None,
+            recent_blockhash,
+            settled_at: None,
+        }
+    }
+
+    fn apply(&mut self, status: TransactionStatus) {
+        self.state = match status.confirmation_status() {
+            TransactionConfirmationStatus::Processed => TxState::Processed,
+            TransactionConfirmationStatus::Confirmed => TxState::Confirmed,
+            TransactionConfirmationStatus::Finalized => TxState::Finalized,
+        };
+        self.slot = Some(status.slot);
+        self.err = status.err;
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    txs: Mutex<HashMap<Signature, TrackedTx>>,
+    updated: Condvar,
+    /// `wait` calls in progress
+    waiters: AtomicUsize,
+    max_waiters: usize,
+}
+
+/// Shared handle, clones watch the same signatures
+#[derive(Clone)]
+pub struct ConfirmationTracker {
+    inner: Arc<Inner>,
+}
+
+/// Counts a `wait` call for as long as it blocks
+struct Waiter<'a>(&'a AtomicUsize);
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ConfirmationTracker {
+    /// At most `max_waiters` threads block in `wait` at a time
+    pub fn new(max_waiters: usize) -> Self {
+        ConfirmationTracker {
+            inner: Arc::new(Inner {
+                max_waiters,
+                ..Inner::default()
+            }),
+        }
+    }
+
+    /// Starts watching `signature`. Without `recent_blockhash` it can't be told expired.
+    pub fn track(&self, signature: Signature, recent_blockhash: Option<Hash>) {
+        let mut txs = self.inner.txs.lock().unwrap();
+        txs.entry(signature).or_insert_with(|| TrackedTx::new(signature, recent_blockhash));
+    }
+
+    /// One-off status of a signature that isn't tracked, old transactions included.
+    /// Nothing is kept, a signature the cluster never saw stays pending.
+    pub fn lookup(client: &RpcClient, signature: &Signature) -> Result<TrackedTx> {
+        let mut tx = TrackedTx::new(*signature, None);
+        let status = client
+            .get_signature_statuses_with_history(&[*signature])?
+            .value
+            .into_iter()
+            .next()
+            .flatten();
+        if let Some(status) = status {
+            tx.apply(status);
+        }
+        Ok(tx)
+    }
+
+    pub fn status(&self, signature: &Signature) -> Option<TrackedTx> {
+        self.inner.txs.lock().unwrap().get(signature).cloned()
+    }
+
+    /// Refreshes every unsettled signature and drops the ones settled longer than `RETENTION`
+    pub fn poll(&self, client: &RpcClient) -> Result<()> {
+        let pending: Vec<Signature> = {
+            let mut txs = self.inner.txs.lock().unwrap();
+            txs.retain(|_, tx| tx.settled_at.map_or(true, |at| at.elapsed() < RETENTION));
+            txs.iter()
+                .filter(|(_, tx)| !tx.state.is_settled())
+                .map(|(signature, _)| *signature)
+                .collect()
+        };
+        self.refresh(client, &pending)
+    }
+
+    /// Fetches the status of tracked `signatures` now
+    pub fn refresh(&self, client: &RpcClient, signatures: &[Signature]) -> Result<()> {
+        let pending: Vec<(Signature, Option<Hash>)> = {
+            let txs = self.inner.txs.lock().unwrap();
+            signatures
+                .iter()
+                .filter_map(|signature| txs.get(signature).map(|tx| (*signature, tx.recent_blockhash)))
+                .collect()
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut updates = Vec::with_capacity(pending.len());
+        for chunk in pending.chunks(MAX_SIGNATURE_STATUSES) {
+            let signatures: Vec<Signature> = chunk.iter().map(|(signature, _)| *signature).collect();
+            let statuses = client.get_signature_statuses(&signatures)?.value;
+            updates.extend(chunk.iter().cloned().zip(statuses));
+        }
+
+        // checked after the statuses, so a transaction landing in between is never called expired
+        let mut expired_blockhashes = HashSet::new();
+        for ((_, recent_blockhash), status) in updates.iter() {
+            if let (None, Some(blockhash)) = (status, recent_blockhash) {
+                if !expired_blockhashes.contains(blockhash)
+                    && !client.is_blockhash_valid(blockhash, CommitmentConfig::processed())?
+                {
+                    expired_blockhashes.insert(*blockhash);
+                }
+            }
+        }
+
+        let mut txs = self.inner.txs.lock().unwrap();
+        for ((signature, recent_blockhash), status) in updates {
+            let tx = match txs.get_mut(&signature) {
+                Some(tx) => tx,
+                None => continue,
+            };
+            match status {
+                Some(status) => tx.apply(status),
+                None => {
+                    if recent_blockhash.map_or(false, |blockhash| expired_blockhashes.contains(&blockhash)) {
+                        tx.state = TxState::Expired;
+                    }
+                }
+            }
+            if tx.state.is_settled() && tx.settled_at.is_none() {
+                tx.settled_at = Some(Instant::now());
+            }
+        }
+        self.inner.updated.notify_all();
+        Ok(())
+    }
+
+    /// Polls with `client` every `interval` on a background thread
+    pub fn start(&self, client: RpcClient, interval: Duration) {
+        let tracker = self.clone();
+        thread::spawn(move || loop {
+            if let Err(e) = tracker.poll(&client) {
+                log::warn!("confirmation poll failed: {}", e);
+            }
+            thread::sleep(interval);
+        });
+    }
+
+    /// Blocks until `signature` reaches `target`, settles or `timeout` passes, returning its latest status.
+    /// Every waiter holds a server worker, past `max_waiters` of them the call fails right away.
+    pub fn wait(&self, signature: &Signature, target: TxState, timeout: Duration) -> Result<Option<TrackedTx>> {
+        let waiting = self.inner.waiters.fetch_add(1, Ordering::SeqCst);
+        let _waiter = Waiter(&self.inner.waiters);
+        if waiting >= self.inner.max_waiters {
+            return Err(Error::TooManyRequests("too many requests waiting for confirmation".to_string()));
+        }
+        let deadline = Instant::now() + timeout;
+        let mut txs = self.inner.txs.lock().unwrap();
+        loop {
+            let tx = match txs.get(signature) {
+                Some(tx) => tx,
+                None => return Ok(None),
+            };
+            let now = Instant::now();
+            if tx.state.reached(target) || tx.state.is_settled() || now >= deadline {
+                return Ok(Some(tx.clone()));
+            }
+            txs = self.inner.updated.wait_timeout(txs, deadline - now).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spins until `count` threads block in `wait`
+    fn wait_for_waiters(tracker: &ConfirmationTracker, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while tracker.inner.waiters.load(Ordering::SeqCst) != count {
+            assert!(Instant::now() < deadline, "waiters never reached {}", count);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn waiters_past_the_cap_are_refused() {
+        let tracker = ConfirmationTracker::new(1);
+        let signature = Signature::new_unique();
+        tracker.track(signature, None);
+
+        let waiting = tracker.clone();
+        let waiter = thread::spawn(move || {
+            waiting.wait(&signature, TxState::Finalized, Duration::from_secs(1))
+        });
+        wait_for_waiters(&tracker, 1);
+        assert!(matches!(
+            tracker.wait(&signature, TxState::Finalized, Duration::from_secs(5)),
+            Err(Error::TooManyRequests(_))
+        ));
+
+        // the first waiter times out with the signature still pending and frees its slot
+        let tx = waiter.join().unwrap().unwrap().unwrap();
+        assert_eq!(tx.state, TxState::Pending);
+        assert_eq!(tracker.inner.waiters.load(Ordering::SeqCst), 0);
+        assert_eq!(tracker.wait(&Signature::new_unique(), TxState::Finalized, Duration::from_secs(5)).unwrap(), None);
+    }
+
+    #[test]
+    fn refresh_wakes_waiters() {
+        let tracker = ConfirmationTracker::new(4);
+        let signature = Signature::new_unique();
+        tracker.track(signature, None);
+
+        let waiting = tracker.clone();
+        let waiter = thread::spawn(move || {
+            let started = Instant::now();
+            let tx = waiting.wait(&signature, TxState::Confirmed, Duration::from_secs(30));
+            (tx, started.elapsed())
+        });
+        wait_for_waiters(&tracker, 1);
+
+        // the mock cluster reports every signature finalized at slot 1
+        let client = RpcClient::new_mock("succeeds".to_string());
+        tracker.refresh(&client, &[signature]).unwrap();
+
+        let (tx, elapsed) = waiter.join().unwrap();
+        let tx = tx.unwrap().unwrap();
+        assert_eq!(tx.state, TxState::Finalized);
+        assert_eq!(tx.slot, Some(1));
+        assert!(elapsed < Duration::from_secs(30));
+        assert!(tracker.status(&signature).unwrap().settled_at.is_some());
+    }
+}
@@ -0,0 +1,220 @@
+//! Errors of the HTTP api
+
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, status, Responder};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_program::program_error::ProgramError;
+use solana_rpc_client_api::custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY;
+use solana_rpc_client_api::request::{RpcError, RpcResponseErrorData};
+use solana_sdk::signer::SignerError;
+use solana_sdk::transaction::TransactionError;
+use thiserror::Error;
+use crate::raydium::error::AmmError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The node could not be reached or reported itself unhealthy
+    #[error("rpc node unavailable: {0}")]
+    RpcUnavailable(String),
+    /// The node answered with a json rpc error
+    #[error("rpc error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    /// The transaction failed preflight or simulation
+    #[error("transaction failed: {err}")]
+    Transaction {
+        err: TransactionError,
+        logs: Vec<String>,
+    },
+    /// Malformed transaction, key, hash or account data
+    #[error("decode error: {0}")]
+    Decode(String),
+    #[error("signing error: {0}")]
+    Signing(#[from] SignerError),
+    /// Missing or invalid credentials
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    /// Valid credentials without the permission the route needs
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    /// No signer is configured under the requested name
+    #[error("unknown signer: {0}")]
+    UnknownSigner(String),
+    #[error("account not found: {0}")]
+    AccountNotFound(String),
+    /// The request is valid but the account is not in a state that allows it yet
+    #[error("invalid state: {0}")]
+    InvalidState(String),
+    /// The server is busy with requests of the same kind, retry later
+    #[error("too many requests: {0}")]
+    TooManyRequests(String),
+    #[error("amm error: {0}")]
+    Amm(AmmError),
+    #[error("program error: {0}")]
+    Program(ProgramError),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Json body of an error response
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_code: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program_code: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<Vec<String>>,
+}
+
+impl Error {
+    /// Stable error code, safe to match on in clients and monitoring
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::RpcUnavailable(_) => "rpc_unavailable",
+            Error::Rpc { .. } => "rpc_error",
+            Error::Transaction { .. } => "transaction_failed",
+            Error::Decode(_) => "decode_error",
+            Error::Signing(_) => "signing_error",
+            Error::UnknownSigner(_) => "unknown_signer",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::AccountNotFound(_) => "account_not_found",
+            Error::InvalidState(_) => "invalid_state",
+            Error::TooManyRequests(_) => "too_many_requests",
+            Error::Amm(_) => "amm_error",
+            Error::Program(_) => "program_error",
+            Error::Other(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            Error::RpcUnavailable(_) => Status::ServiceUnavailable,
+            Error::Rpc { .. } => Status::BadGateway,
+            Error::Transaction { .. } | Error::Amm(_) | Error::Program(_) => {
+                Status::UnprocessableEntity
+            }
+            Error::Decode(_) | Error::UnknownSigner(_) => Status::BadRequest,
+            Error::AccountNotFound(_) => Status::NotFound,
+            Error::Unauthorized(_) => Status::Unauthorized,
+            Error::Forbidden(_) => Status::Forbidden,
+            Error::InvalidState(_) => Status::Conflict,
+            Error::TooManyRequests(_) => Status::TooManyRequests,
+            Error::Signing(_) | Error::Other(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            code: self.code(),
+            message: self.to_string(),
+            rpc_code: match self {
+                Error::Rpc { code, .. } => Some(*code),
+                _ => None,
+            },
+            program_code: match self {
+                Error::Amm(e) => Some(e.clone() as u32),
+                Error::Program(ProgramError::Custom(code)) => Some(*code),
+                _ => None,
+            },
+            logs: match self {
+                Error::Transaction { logs, .. } => Some(logs.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<ClientError> for Error {
+    fn from(e: ClientError) -> Self {
+        let message = e.to_string();
+        match e.kind {
+            ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => Error::RpcUnavailable(message),
+            ClientErrorKind::RpcError(RpcError::RpcRequestError(_)) => {
+                Error::RpcUnavailable(message)
+            }
+            ClientErrorKind::RpcError(RpcError::RpcResponseError { code, message, data }) => {
+                match data {
+                    RpcResponseErrorData::SendTransactionPreflightFailure(result) => {
+                        match result.err {
+                            Some(err) => Error::Transaction {
+                                err,
+                                logs: result.logs.unwrap_or_default(),
+                            },
+                            None => Error::Rpc { code, message },
+                        }
+                    }
+                    _ if code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY => {
+                        Error::RpcUnavailable(message)
+                    }
+                    _ => Error::Rpc { code, message },
+                }
+            }
+            ClientErrorKind::SerdeJson(_) => Error::Decode(message),
+            ClientErrorKind::SigningError(e) => Error::Signing(e),
+            ClientErrorKind::TransactionError(err) => Error::Transaction { err, logs: vec![] },
+            _ => Error::Other(message),
+        }
+    }
+}
+
+/// Custom codes are only known to the program that returned them, they stay `Error::Program`.
+/// Raydium results convert their `AmmError` with `From<AmmError>` instead.
+impl From<ProgramError> for Error {
+    fn from(e: ProgramError) -> Self {
+        Error::Program(e)
+    }
+}
+
+impl From<AmmError> for Error {
+    fn from(e: AmmError) -> Self {
+        Error::Amm(e)
+    }
+}
+
+impl From<solana_sdk::pubkey::ParsePubkeyError> for Error {
+    fn from(e: solana_sdk::pubkey::ParsePubkeyError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<solana_sdk::hash::ParseHashError> for Error {
+    fn from(e: solana_sdk::hash::ParseHashError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        status::Custom(self.status(), Json(self.to_response())).respond_to(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_program_errors_are_not_amm_errors() {
+        let e = Error::from(ProgramError::Custom(AmmError::InvalidStatus as u32));
+        assert_eq!(e.code(), "program_error");
+        assert_eq!(e.to_response().program_code, Some(AmmError::InvalidStatus as u32));
+
+        let e = Error::from(AmmError::InvalidStatus);
+        assert_eq!(e.code(), "amm_error");
+        assert_eq!(e.to_response().program_code, Some(AmmError::InvalidStatus as u32));
+    }
+}
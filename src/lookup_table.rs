@@ -0,0 +1,372 @@
+//! Address lookup table lifecycle: create, extend, deactivate and close
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use anyhow::anyhow;
+use rocket::config::ConfigError;
+use solana_client::rpc_client::RpcClient;
+use solana_program::address_lookup_table::state::{
+    AddressLookupTable, LookupTableMeta, LOOKUP_TABLE_MAX_ADDRESSES,
+};
+use solana_sdk::address_lookup_table::instruction::{
+    close_lookup_table, create_lookup_table, deactivate_lookup_table, extend_lookup_table,
+};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::slot_hashes::MAX_ENTRIES;
+use solana_sdk::transaction::Transaction;
+use crate::account_store::MAX_MULTIPLE_ACCOUNTS;
+use crate::error::{Error, Result};
+
+/// Bytes a v0 message saves per account key loaded through a table instead of listed statically
+pub const BYTES_SAVED_PER_KEY: i64 = 31;
+/// Bytes a v0 message spends per table it loads from, the key plus two index array lengths
+pub const BYTES_PER_TABLE: i64 = 34;
+
+/// Decoded lookup table account
+#[derive(Clone, Debug, PartialEq)]
+pub struct LookupTable {
+    pub key: Pubkey,
+    pub meta: LookupTableMeta,
+    pub addresses: Vec<Pubkey>,
+}
+
+impl LookupTable {
+    pub fn is_active(&self) -> bool {
+        self.meta.deactivation_slot == u64::MAX
+    }
+
+    /// First slot the table can be closed at, `None` while it is active.
+    /// A deactivated table is usable until its deactivation slot leaves the SlotHashes sysvar.
+    pub fn closeable_slot(&self) -> Option<u64> {
+        if self.is_active() {
+            None
+        } else {
+            Some(self.meta.deactivation_slot.saturating_add(MAX_ENTRIES as u64 + 1))
+        }
+    }
+}
+
+pub fn fetch_lookup_table(client: &RpcClient, address: &Pubkey) -> Result<LookupTable> {
+    let account = client
+        .get_account_with_commitment(address, client.commitment())?
+        .value
+        .ok_or_else(|| Error::AccountNotFound(address.to_string()))?;
+    decode_lookup_table(address, &account.data)
+}
+
+fn decode_lookup_table(address: &Pubkey, data: &[u8]) -> Result<LookupTable> {
+    let table = AddressLookupTable::deserialize(data)
+        .map_err(|e| Error::Decode(format!("lookup table {}: {}", address, e)))?;
+    Ok(LookupTable {
+        key: *address,
+        meta: table.meta,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+/// Loads `addresses` in as few requests as possible, missing or undecodable tables are left out
+pub fn fetch_lookup_tables(client: &RpcClient, addresses: &[Pubkey]) -> Result<Vec<LookupTable>> {
+    let mut tables = Vec::new();
+    for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = client.get_multiple_accounts(chunk)?;
+        for (address, account) in chunk.iter().zip(accounts) {
+            if let Some(account) = account {
+                if let Ok(table) = decode_lookup_table(address, &account.data) {
+                    tables.push(table);
+                }
+            }
+        }
+    }
+    Ok(tables)
+}
+
+/// Keys of one table that a set of keys could be loaded through
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableCoverage {
+    pub table: Pubkey,
+    pub covered: Vec<Pubkey>,
+    /// message bytes saved if only this table is used, negative when it costs more than it saves
+    pub bytes_saved: i64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coverage {
+    /// every table holding at least one of the keys, most covered keys first
+    pub tables: Vec<TableCoverage>,
+    /// tables picked greedily to save the most bytes
+    pub selected: Vec<Pubkey>,
+    pub bytes_saved: i64,
+    /// keys no selected table holds
+    pub uncovered: Vec<Pubkey>,
+}
+
+/// Which of `tables` hold `keys` and how many bytes a v0 message would save using them.
+/// Signers and invoked program ids can't be loaded from a table, leave them out of `keys`.
+pub fn coverage(tables: &[LookupTable], keys: &[Pubkey]) -> Coverage {
+    let keys: Vec<Pubkey> = {
+        let mut seen = HashSet::new();
+        keys.iter().filter(|key| seen.insert(**key)).cloned().collect()
+    };
+    let mut table_coverages: Vec<TableCoverage> = tables
+        .iter()
+        .filter(|table| table.is_active())
+        .filter_map(|table| {
+            let held: HashSet<&Pubkey> = table.addresses.iter().collect();
+            let covered: Vec<Pubkey> = keys.iter().filter(|key| held.contains(key)).cloned().collect();
+            if covered.is_empty() {
+                None
+            } else {
+                Some(TableCoverage {
+                    table: table.key,
+                    bytes_saved: covered.len() as i64 * BYTES_SAVED_PER_KEY - BYTES_PER_TABLE,
+                    covered,
+                })
+            }
+        })
+        .collect();
+    table_coverages.sort_by(|a, b| b.covered.len().cmp(&a.covered.len()));
+
+    let mut uncovered: HashSet<Pubkey> = keys.iter().cloned().collect();
+    let mut selected = Vec::new();
+    let mut bytes_saved = 0;
+    loop {
+        let best = table_coverages
+            .iter()
+            .filter(|table| !selected.contains(&table.table))
+            .map(|table| {
+                let count = table.covered.iter().filter(|key| uncovered.contains(key)).count();
+                (table, count as i64 * BYTES_SAVED_PER_KEY - BYTES_PER_TABLE)
+            })
+            .max_by_key(|(_, saved)| *saved);
+        match best {
+            Some((table, saved)) if saved > 0 => {
+                for key in table.covered.iter() {
+                    uncovered.remove(key);
+                }
+                selected.push(table.table);
+                bytes_saved += saved;
+            }
+            _ => break,
+        }
+    }
+
+    Coverage {
+        tables: table_coverages,
+        selected,
+        bytes_saved,
+        uncovered: keys.into_iter().filter(|key| uncovered.contains(key)).collect(),
+    }
+}
+
+fn send(
+    client: &RpcClient,
+    instructions: &[Instruction],
+    payer: &Keypair,
+    signers: &[&Keypair],
+) -> Result<Signature> {
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let mut tx = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+    tx.try_sign(signers, recent_blockhash)?;
+    Ok(client.send_and_confirm_transaction(&tx)?)
+}
+
+/// Creates a table owned by `authority` at the address derived from a recent finalized slot
+pub fn create(client: &RpcClient, payer: &Keypair, authority: &Keypair) -> Result<(Pubkey, Signature)> {
+    let recent_slot = client.get_slot_with_commitment(CommitmentConfig::finalized())?;
+    let (create_ix, address) = create_lookup_table(authority.pubkey(), payer.pubkey(), recent_slot);
+    // the authority does not sign the create
+    let signature = send(client, &[create_ix], payer, &[payer])?;
+    Ok((address, signature))
+}
+
+/// Splits `addresses` into extend instructions that each fit in one transaction
+pub fn extend_instructions(
+    payer: &Pubkey,
+    authority: &Pubkey,
+    address: &Pubkey,
+    addresses: &[Pubkey],
+) -> Result<Vec<Instruction>> {
+    let mut instructions = Vec::new();
+    let mut chunk: Vec<Pubkey> = Vec::new();
+    for new_address in addresses {
+        chunk.push(*new_address);
+        let ix = extend_lookup_table(*address, *authority, Some(*payer), chunk.clone());
+        let tx = Transaction::new_with_payer(&[ix], Some(payer));
+        if bincode::serialized_size(&tx)? as usize > PACKET_DATA_SIZE {
+            chunk.pop();
+            if chunk.is_empty() {
+                return Err(Error::Other("extend instruction too large".to_string()));
+            }
+            instructions.push(extend_lookup_table(*address, *authority, Some(*payer), chunk));
+            chunk = vec![*new_address];
+        }
+    }
+    if !chunk.is_empty() {
+        instructions.push(extend_lookup_table(*address, *authority, Some(*payer), chunk));
+    }
+    Ok(instructions)
+}
+
+/// Adds the `addresses` the table does not hold yet, one transaction per chunk
+pub fn extend(
+    client: &RpcClient,
+    payer: &Keypair,
+    authority: &Keypair,
+    address: &Pubkey,
+    addresses: &[Pubkey],
+) -> Result<Vec<Signature>> {
+    let table = fetch_lookup_table(client, address)?;
+    if !table.is_active() {
+        return Err(Error::InvalidState(format!("lookup table {} is deactivated", address)));
+    }
+    let mut known: HashSet<Pubkey> = table.addresses.iter().cloned().collect();
+    let new_addresses: Vec<Pubkey> = addresses
+        .iter()
+        .filter(|key| known.insert(**key))
+        .cloned()
+        .collect();
+    if table.addresses.len() + new_addresses.len() > LOOKUP_TABLE_MAX_ADDRESSES {
+        return Err(Error::InvalidState(format!(
+            "lookup table {} would hold {} addresses, max {}",
+            address,
+            table.addresses.len() + new_addresses.len(),
+            LOOKUP_TABLE_MAX_ADDRESSES
+        )));
+    }
+    extend_instructions(&payer.pubkey(), &authority.pubkey(), address, &new_addresses)?
+        .into_iter()
+        .map(|ix| send(client, &[ix], payer, &[payer, authority]))
+        .collect()
+}
+
+pub fn deactivate(
+    client: &RpcClient,
+    payer: &Keypair,
+    authority: &Keypair,
+    address: &Pubkey,
+) -> Result<Signature> {
+    let table = fetch_lookup_table(client, address)?;
+    if !table.is_active() {
+        return Err(Error::InvalidState(format!("lookup table {} is already deactivated", address)));
+    }
+    let ix = deactivate_lookup_table(*address, authority.pubkey());
+    send(client, &[ix], payer, &[payer, authority])
+}
+
+/// Closes a deactivated table once its cooldown is over, the rent goes to `recipient`
+pub fn close(
+    client: &RpcClient,
+    payer: &Keypair,
+    authority: &Keypair,
+    address: &Pubkey,
+    recipient: &Pubkey,
+) -> Result<Signature> {
+    let table = fetch_lookup_table(client, address)?;
+    let closeable_slot = table
+        .closeable_slot()
+        .ok_or_else(|| Error::InvalidState(format!("lookup table {} is active", address)))?;
+    let slot = client.get_slot_with_commitment(CommitmentConfig::finalized())?;
+    if slot < closeable_slot {
+        return Err(Error::InvalidState(format!(
+            "lookup table {} can be closed from slot {}, current slot {}",
+            address, closeable_slot, slot
+        )));
+    }
+    let ix = close_lookup_table(*address, authority.pubkey(), *recipient);
+    send(client, &[ix], payer, &[payer, authority])
+}
+
+/// Tables the coverage query searches, from the `known_alts` array extra of Rocket.toml.
+/// Not setting `known_alts` means no known tables, a malformed one is a config error.
+pub struct KnownLookupTables {
+    pub addresses: Vec<Pubkey>,
+}
+
+impl KnownLookupTables {
+    pub fn from_config(config: &rocket::Config) -> anyhow::Result<Self> {
+        let addresses = match config.get_slice("known_alts") {
+            Ok(values) => values
+                .iter()
+                .map(|value| {
+                    let address = value
+                        .as_str()
+                        .ok_or_else(|| anyhow!("known_alts must hold strings"))?;
+                    Pubkey::from_str(address)
+                        .map_err(|e| anyhow!("invalid known_alts address {}: {}", address, e))
+                })
+                .collect::<anyhow::Result<Vec<Pubkey>>>()?,
+            Err(ConfigError::Missing(_)) => vec![],
+            Err(e) => return Err(anyhow!("invalid known_alts: {}", e)),
+        };
+        Ok(KnownLookupTables { addresses })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::address_lookup_table::instruction::ProgramInstruction;
+
+    fn table(deactivation_slot: u64) -> LookupTable {
+        LookupTable {
+            key: Pubkey::new_unique(),
+            meta: LookupTableMeta {
+                deactivation_slot,
+                ..LookupTableMeta::default()
+            },
+            addresses: vec![],
+        }
+    }
+
+    fn extended_addresses(ix: &Instruction) -> Vec<Pubkey> {
+        match bincode::deserialize(&ix.data).unwrap() {
+            ProgramInstruction::ExtendLookupTable { new_addresses } => new_addresses,
+            other => panic!("not an extend instruction: {:?}", other),
+        }
+    }
+
+    fn transaction_size(ix: &Instruction, payer: &Pubkey) -> usize {
+        let tx = Transaction::new_with_payer(&[ix.clone()], Some(payer));
+        bincode::serialized_size(&tx).unwrap() as usize
+    }
+
+    #[test]
+    fn extend_instructions_fill_packets_in_order() {
+        let payer = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let address = Pubkey::new_unique();
+        let addresses: Vec<Pubkey> = (0..LOOKUP_TABLE_MAX_ADDRESSES).map(|_| Pubkey::new_unique()).collect();
+
+        let instructions = extend_instructions(&payer, &authority, &address, &addresses).unwrap();
+        assert!(instructions.len() > 1);
+        let chunks: Vec<Vec<Pubkey>> = instructions.iter().map(extended_addresses).collect();
+        assert_eq!(chunks.concat(), addresses);
+        for (index, ix) in instructions.iter().enumerate() {
+            assert!(transaction_size(ix, &payer) <= PACKET_DATA_SIZE);
+            // every chunk but the last is full, one more address would not fit
+            if let Some(next) = chunks.get(index + 1) {
+                let mut grown = chunks[index].clone();
+                grown.push(next[0]);
+                let grown = extend_lookup_table(address, authority, Some(payer), grown);
+                assert!(transaction_size(&grown, &payer) > PACKET_DATA_SIZE);
+            }
+        }
+
+        assert!(extend_instructions(&payer, &authority, &address, &[]).unwrap().is_empty());
+        let one = extend_instructions(&payer, &authority, &address, &addresses[..1]).unwrap();
+        assert_eq!(one.iter().map(extended_addresses).collect::<Vec<_>>(), vec![addresses[..1].to_vec()]);
+    }
+
+    #[test]
+    fn closeable_slot_waits_out_slot_hashes() {
+        assert_eq!(table(u64::MAX).closeable_slot(), None);
+        // the deactivation slot stays in SlotHashes for MAX_ENTRIES slots after it
+        assert_eq!(table(1_000).closeable_slot(), Some(1_000 + MAX_ENTRIES as u64 + 1));
+        assert_eq!(table(0).closeable_slot(), Some(MAX_ENTRIES as u64 + 1));
+        assert_eq!(table(u64::MAX - 1).closeable_slot(), Some(u64::MAX));
+    }
+}
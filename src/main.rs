@@ -2,11 +2,21 @@
 #![feature(decl_macro)]
 #![feature(total_cmp)]
 
+pub mod account_diff;
+pub mod account_store;
 pub mod api;
+pub mod auth;
+pub mod confirmation;
+pub mod error;
+pub mod lookup_table;
 pub mod node_client;
 pub mod raydium;
 pub mod pool_test;
+pub mod signer;
+pub mod snapshot;
 pub mod solfi;
+pub mod swap_events;
+pub mod transaction_builder;
 
 #[macro_use]
 extern crate rocket;
@@ -27,14 +37,101 @@ extern crate safe_transmute;
 extern crate thiserror;
 extern crate bincode;
 
+use std::str::FromStr;
+use std::time::Duration;
 use rocket::http::Method;
+use rocket::State;
 use rocket_contrib::json::Json;
 use rocket_cors::{Cors, AllowedOrigins, AllowedHeaders};
 use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccount;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+use solana_sdk::transaction::TransactionError;
+use solana_transaction_status::{UiInnerInstructions, UiTransactionReturnData};
+use crate::account_diff::AccountDiff;
+use crate::api::{SendTxRequest, SimulateTxRequest};
+use crate::confirmation::{ConfirmationTracker, TrackedTx, TxState};
+use crate::auth::{ApiKeys, Auth, ReadAccess, SignAccess, SignedJson, SubmitAccess};
+use crate::error::{Error, ErrorResponse, Result};
+use crate::lookup_table::KnownLookupTables;
+use crate::signer::{SignerStore, DEFAULT_SIGNER};
+use crate::node_client::NetworkOpts;
+
+/// Longest a status request may block
+const MAX_WAIT_SECS: u64 = 10;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SimulateResponse {
+    pub err: Option<TransactionError>,
     pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+    pub return_data: Option<UiTransactionReturnData>,
+    pub inner_instructions: Option<Vec<UiInnerInstructions>>,
+    /// post-simulation state of the requested accounts, in request order
+    pub accounts: Vec<SimulatedAccount>,
+    /// requested accounts decoded before and after the simulation
+    pub diffs: Vec<AccountDiff>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimulatedAccount {
+    pub pubkey: String,
+    pub account: Option<UiAccount>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SendTxResponse {
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AltExtendRequest {
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AltResponse {
+    pub address: String,
+    pub signatures: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AltInfoResponse {
+    pub address: String,
+    pub authority: Option<String>,
+    /// `None` while the table is active
+    pub deactivation_slot: Option<u64>,
+    pub last_extended_slot: u64,
+    pub last_extended_slot_start_index: u8,
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AltCoverageRequest {
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AltTableCoverage {
+    pub address: String,
+    pub covered: Vec<String>,
+    pub bytes_saved: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AltCoverageResponse {
+    pub tables: Vec<AltTableCoverage>,
+    pub selected: Vec<String>,
+    pub bytes_saved: i64,
+    pub uncovered: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignerResponse {
+    pub name: String,
+    pub pubkey: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -49,60 +146,241 @@ fn index() -> &'static str {
 }
 
 #[get("/get_blockhash")]
-fn get_blockhash() -> String {
-    api::get_blockhash()
+fn get_blockhash(_auth: Auth<ReadAccess>, client: State<RpcClient>) -> Result<String> {
+    api::get_blockhash(&client)
 }
 
 #[get("/get_hash_and_slot")]
-fn get_hash_and_slot() -> Json<BlockSlotResponse> {
-    let block = api::get_hash_and_slot();
-    Json(BlockSlotResponse {
+fn get_hash_and_slot(_auth: Auth<ReadAccess>, client: State<RpcClient>) -> Result<Json<BlockSlotResponse>> {
+    let block = api::get_hash_and_slot(&client)?;
+    Ok(Json(BlockSlotResponse {
         hash: block.0,
         slot: block.1
-    })
+    }))
 }
 
 #[get("/get_slot")]
-fn get_slot() -> String {
-    api::get_slot()
+fn get_slot(_auth: Auth<ReadAccess>, client: State<RpcClient>) -> Result<String> {
+    api::get_slot(&client)
+}
+
+#[post("/tx/send", format = "json", data = "<request>")]
+fn send_tx(_auth: Auth<SubmitAccess>, client: State<RpcClient>, tracker: State<ConfirmationTracker>, request: SignedJson<SendTxRequest>) -> Result<Json<SendTxResponse>> {
+    let (signature, recent_blockhash) = api::send_tx_with_options(&client, &request)?;
+    tracker.track(signature, Some(recent_blockhash));
+    Ok(Json(SendTxResponse {
+        signature: signature.to_string()
+    }))
+}
+
+/// Status of a signature, `wait` blocks until it reaches that commitment or `timeout_secs` pass.
+/// Signatures not sent through `/tx/send` are looked up once and can't be waited on.
+#[get("/tx/<signature>/status?<wait>&<timeout_secs>")]
+fn tx_status(_auth: Auth<ReadAccess>, client: State<RpcClient>, tracker: State<ConfirmationTracker>, signature: String, wait: Option<String>, timeout_secs: Option<u64>) -> Result<Json<TrackedTx>> {
+    let signature = Signature::from_str(&signature)
+        .map_err(|e| Error::Decode(format!("invalid signature: {}", e)))?;
+    if tracker.status(&signature).is_none() {
+        return Ok(Json(ConfirmationTracker::lookup(&client, &signature)?));
+    }
+    let status = match wait {
+        Some(wait) => {
+            let timeout = Duration::from_secs(timeout_secs.unwrap_or(MAX_WAIT_SECS).min(MAX_WAIT_SECS));
+            tracker.wait(&signature, TxState::parse(&wait)?, timeout)?
+        }
+        None => tracker.status(&signature),
+    };
+    status
+        .map(Json)
+        .ok_or_else(|| Error::Other(format!("signature {} dropped from tracking", signature)))
+}
+
+#[get("/signers")]
+fn list_signers(_auth: Auth<ReadAccess>, signers: State<SignerStore>) -> Json<Vec<SignerResponse>> {
+    Json(signers
+        .pubkeys()
+        .into_iter()
+        .map(|(name, pubkey)| SignerResponse {
+            name,
+            pubkey: pubkey.to_string()
+        })
+        .collect())
+}
+
+#[get("/alt/<address>")]
+fn alt_info(_auth: Auth<ReadAccess>, client: State<RpcClient>, address: String) -> Result<Json<AltInfoResponse>> {
+    let table = lookup_table::fetch_lookup_table(&client, &Pubkey::from_str(&address)?)?;
+    Ok(Json(AltInfoResponse {
+        deactivation_slot: if table.is_active() { None } else { Some(table.meta.deactivation_slot) },
+        address,
+        authority: table.meta.authority.map(|authority| authority.to_string()),
+        last_extended_slot: table.meta.last_extended_slot,
+        last_extended_slot_start_index: table.meta.last_extended_slot_start_index,
+        addresses: table.addresses.iter().map(|key| key.to_string()).collect()
+    }))
+}
+
+#[post("/alt/coverage", format = "json", data = "<request>")]
+fn alt_coverage(_auth: Auth<ReadAccess>, client: State<RpcClient>, known: State<KnownLookupTables>, request: SignedJson<AltCoverageRequest>) -> Result<Json<AltCoverageResponse>> {
+    let keys = request
+        .addresses
+        .iter()
+        .map(|key| Pubkey::from_str(key))
+        .collect::<std::result::Result<Vec<Pubkey>, _>>()?;
+    let tables = lookup_table::fetch_lookup_tables(&client, &known.addresses)?;
+    let coverage = lookup_table::coverage(&tables, &keys);
+    Ok(Json(AltCoverageResponse {
+        tables: coverage
+            .tables
+            .iter()
+            .map(|table| AltTableCoverage {
+                address: table.table.to_string(),
+                covered: table.covered.iter().map(|key| key.to_string()).collect(),
+                bytes_saved: table.bytes_saved,
+            })
+            .collect(),
+        selected: coverage.selected.iter().map(|key| key.to_string()).collect(),
+        bytes_saved: coverage.bytes_saved,
+        uncovered: coverage.uncovered.iter().map(|key| key.to_string()).collect()
+    }))
+}
+
+#[post("/alt/create?<signer>")]
+fn alt_create(_auth: Auth<SignAccess>, client: State<RpcClient>, signers: State<SignerStore>, signer: Option<String>) -> Result<Json<AltResponse>> {
+    let keypair = signers.get(signer.as_deref().unwrap_or(DEFAULT_SIGNER))?;
+    let (address, signature) = lookup_table::create(&client, keypair, keypair)?;
+    Ok(Json(AltResponse {
+        address: address.to_string(),
+        signatures: vec![signature.to_string()]
+    }))
+}
+
+#[post("/alt/<address>/extend?<signer>", format = "json", data = "<request>")]
+fn alt_extend(_auth: Auth<SignAccess>, client: State<RpcClient>, signers: State<SignerStore>, address: String, signer: Option<String>, request: SignedJson<AltExtendRequest>) -> Result<Json<AltResponse>> {
+    let keypair = signers.get(signer.as_deref().unwrap_or(DEFAULT_SIGNER))?;
+    let table = Pubkey::from_str(&address)?;
+    let addresses = request
+        .addresses
+        .iter()
+        .map(|key| Pubkey::from_str(key))
+        .collect::<std::result::Result<Vec<Pubkey>, _>>()?;
+    let signatures = lookup_table::extend(&client, keypair, keypair, &table, &addresses)?;
+    Ok(Json(AltResponse {
+        address,
+        signatures: signatures.iter().map(|signature| signature.to_string()).collect()
+    }))
+}
+
+#[post("/alt/<address>/deactivate?<signer>")]
+fn alt_deactivate(_auth: Auth<SignAccess>, client: State<RpcClient>, signers: State<SignerStore>, address: String, signer: Option<String>) -> Result<Json<AltResponse>> {
+    let keypair = signers.get(signer.as_deref().unwrap_or(DEFAULT_SIGNER))?;
+    let signature = lookup_table::deactivate(&client, keypair, keypair, &Pubkey::from_str(&address)?)?;
+    Ok(Json(AltResponse {
+        address,
+        signatures: vec![signature.to_string()]
+    }))
+}
+
+#[post("/alt/<address>/close?<signer>")]
+fn alt_close(_auth: Auth<SignAccess>, client: State<RpcClient>, signers: State<SignerStore>, address: String, signer: Option<String>) -> Result<Json<AltResponse>> {
+    let keypair = signers.get(signer.as_deref().unwrap_or(DEFAULT_SIGNER))?;
+    let signature = lookup_table::close(&client, keypair, keypair, &Pubkey::from_str(&address)?, &keypair.pubkey())?;
+    Ok(Json(AltResponse {
+        address,
+        signatures: vec![signature.to_string()]
+    }))
 }
 
-#[get("/send_tx?<tx>")]
-fn send_tx(tx: String) -> String {
-    let hash = api::send_tx(&tx);
-    hash
+#[post("/tx/simulate", format = "json", data = "<request>")]
+fn simulate_tx(_auth: Auth<ReadAccess>, client: State<RpcClient>, request: SignedJson<SimulateTxRequest>) -> Result<Json<SimulateResponse>> {
+    let (result, diffs) = api::simulate_tx(&client, &request)?;
+    let accounts = request
+        .accounts
+        .iter()
+        .zip(result.accounts.unwrap_or_default())
+        .map(|(pubkey, account)| SimulatedAccount {
+            pubkey: pubkey.clone(),
+            account,
+        })
+        .collect();
+    Ok(Json(SimulateResponse {
+        err: result.err,
+        logs: result.logs.unwrap_or_default(),
+        units_consumed: result.units_consumed,
+        return_data: result.return_data,
+        inner_instructions: result.inner_instructions,
+        accounts,
+        diffs,
+    }))
 }
 
-#[get("/close?<account>")]
-fn close(account: String) -> String {
-    let hash = api::close(&account);
-    hash
+#[catch(401)]
+fn unauthorized() -> Json<ErrorResponse> {
+    Json(Error::Unauthorized("missing or invalid credentials".to_string()).to_response())
 }
 
-#[get("/simulate?<tx>")]
-fn simulate_tx(tx: String) -> Json<SimulateResponse> {
-    let logs = api::simulate_tx(&tx);
-    Json(SimulateResponse {
-        logs
-    })
+#[catch(403)]
+fn forbidden() -> Json<ErrorResponse> {
+    Json(Error::Forbidden("api key lacks the permission this route needs".to_string()).to_response())
 }
 
 fn main() {
-    // api::get_solfi_accounts();
-    // api::get_solfi_account();
-    rocket::ignite()
-        .mount("/", routes![index,get_blockhash,send_tx,simulate_tx,get_hash_and_slot,get_slot,close])
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("encrypt-keystore") {
+        match signer::encrypt_keystore(&args[2..]) {
+            Ok(pubkey) => println!("keystore written for {}", pubkey),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let rocket = rocket::ignite();
+    let network = NetworkOpts::from_config(rocket.config()).expect("rpc config error");
+    let signers = SignerStore::from_config(rocket.config()).expect("signers config error");
+    let known_alts = KnownLookupTables::from_config(rocket.config()).expect("known alts config error");
+    let api_keys = ApiKeys::from_config(rocket.config()).expect("api keys config error");
+    let cors = get_cors(rocket.config());
+    let poll_interval = rocket.config().get_int("tx_poll_interval_ms").unwrap_or(1000).max(100) as u64;
+    // waits hold a worker each, leave the other half for everything else
+    let tracker = ConfirmationTracker::new((rocket.config().workers as usize / 2).max(1));
+    tracker.start(network.rpc_client(), Duration::from_millis(poll_interval));
+    // one client for all routes, its http connections are pooled and kept alive between requests
+    rocket
+        .manage(network.rpc_client())
+        .manage(signers)
+        .manage(known_alts)
+        .manage(api_keys)
+        .manage(tracker)
+        .attach(cors)
+        .register(catchers![unauthorized,forbidden])
+        .mount("/", routes![index,get_blockhash,send_tx,simulate_tx,get_hash_and_slot,get_slot,tx_status,list_signers,
+            alt_info,alt_coverage,alt_create,alt_extend,alt_deactivate,alt_close])
         .launch();
 }
 
-fn get_cors() -> Cors {
-    let allowed_origins = AllowedOrigins::All;
+/// Origins from the `cors_allowed_origins` array of Rocket.toml, no cross origin requests when unset
+fn get_cors(config: &rocket::Config) -> Cors {
+    let origins: Vec<String> = config
+        .get_slice("cors_allowed_origins")
+        .map(|values| values.iter().filter_map(|value| value.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+    let allowed_origins = AllowedOrigins::some_exact(&origins);
     rocket_cors::CorsOptions {
         allowed_origins,
         allowed_methods: vec![Method::Get, Method::Post, Method::Options].into_iter()
             .map(From::from).collect(),
-        allowed_headers: AllowedHeaders::All,
-        allow_credentials: true,
+        allowed_headers: AllowedHeaders::some(&[
+            "Content-Type",
+            "X-Api-Key",
+            "X-Api-Key-Id",
+            "X-Timestamp",
+            "X-Nonce",
+            "X-Content-Sha256",
+            "X-Signature",
+        ]),
+        // credentials travel in headers, never cookies
+        allow_credentials: false,
         ..Default::default()
     }.to_cors().expect("cors config error")
 }
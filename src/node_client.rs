@@ -1,12 +1,91 @@
+use std::env;
 use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use rocket::config::ConfigError;
 use solana_sdk::{signature::Keypair, signer::Signer};
 use solana_client::{client_error::Result as ClientResult, rpc_client::RpcClient};
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::pubkey::Pubkey;
 
+/// Rpc timeout when none is configured
+pub const DEFAULT_RPC_TIMEOUT_SECS: u64 = 30;
+
+/// Rpc endpoint settings.
+/// Read from the `rpc_url`, `rpc_commitment` and `rpc_timeout_secs` extras of Rocket.toml,
+/// or from the `ROCKET_RPC_URL`, `ROCKET_RPC_COMMITMENT` and `ROCKET_RPC_TIMEOUT_SECS`
+/// environment variables Rocket maps onto them.
+#[derive(Clone, Debug)]
 pub struct NetworkOpts {
-    url: String,
+    pub url: String,
+    pub commitment: CommitmentConfig,
+    pub timeout: Duration,
+}
+
+impl NetworkOpts {
+    pub fn new(url: impl Into<String>) -> Self {
+        NetworkOpts {
+            url: url.into(),
+            commitment: CommitmentConfig::confirmed(),
+            timeout: Duration::from_secs(DEFAULT_RPC_TIMEOUT_SECS),
+        }
+    }
+
+    fn parse(url: String, commitment: Option<&str>, timeout_secs: Option<u64>) -> Result<Self> {
+        let mut opts = NetworkOpts::new(url);
+        if let Some(commitment) = commitment {
+            opts.commitment = CommitmentConfig::from_str(commitment)
+                .map_err(|_| anyhow!("invalid rpc_commitment: {}", commitment))?;
+        }
+        if let Some(timeout_secs) = timeout_secs {
+            opts.timeout = Duration::from_secs(timeout_secs);
+        }
+        Ok(opts)
+    }
+
+    /// Settings from the Rocket config, `NetworkType::MainTx` when `rpc_url` is not set
+    pub fn from_config(config: &rocket::Config) -> Result<Self> {
+        let url = match config.get_str("rpc_url") {
+            Ok(url) => url.to_string(),
+            Err(ConfigError::Missing(_)) => NetworkType::MainTx.url().to_string(),
+            Err(e) => return Err(anyhow!("invalid rpc_url: {}", e)),
+        };
+        let commitment = match config.get_str("rpc_commitment") {
+            Ok(commitment) => Some(commitment),
+            Err(ConfigError::Missing(_)) => None,
+            Err(e) => return Err(anyhow!("invalid rpc_commitment: {}", e)),
+        };
+        let timeout_secs = match config.get_int("rpc_timeout_secs") {
+            Ok(timeout_secs) if timeout_secs > 0 => Some(timeout_secs as u64),
+            Ok(timeout_secs) => return Err(anyhow!("invalid rpc_timeout_secs: {}", timeout_secs)),
+            Err(ConfigError::Missing(_)) => None,
+            Err(e) => return Err(anyhow!("invalid rpc_timeout_secs: {}", e)),
+        };
+        Self::parse(url, commitment, timeout_secs)
+    }
+
+    /// Settings from the environment alone, for callers that run without Rocket
+    pub fn from_env() -> Result<Self> {
+        let url = env::var("ROCKET_RPC_URL").unwrap_or_else(|_| NetworkType::MainTx.url().to_string());
+        let commitment = env::var("ROCKET_RPC_COMMITMENT").ok();
+        let timeout_secs = match env::var("ROCKET_RPC_TIMEOUT_SECS") {
+            Ok(timeout_secs) => Some(
+                timeout_secs
+                    .parse::<u64>()
+                    .map_err(|_| anyhow!("invalid ROCKET_RPC_TIMEOUT_SECS: {}", timeout_secs))?,
+            ),
+            Err(_) => None,
+        };
+        Self::parse(url, commitment.as_deref(), timeout_secs)
+    }
+
+    /// Client whose http connections are pooled and reused, `timeout` bounds each rpc request.
+    /// Build it once and share it, constructing one per call throws the pool away.
+    pub fn rpc_client(&self) -> RpcClient {
+        RpcClient::new_with_timeout_and_commitment(self.url.clone(), self.timeout, self.commitment)
+    }
 }
 
 pub enum NetworkType {
@@ -23,9 +102,9 @@ impl NetworkType {
         match self {
             //https://mainnet-beta.solflare.network
             NetworkType::Devnet => "https://hk32.rpcpool.com",//https://psytrbhymqlkfrhudd.dev.genesysgo.net:8899   https://hk32.rpcpool.com
-            NetworkType::DevTx => "x",
+            NetworkType::DevTx => "https://api.devnet.solana.com",
             NetworkType::Mainnet => "https://mainnet.rpcpool.com",
-            NetworkType::MainTx => "x",
+            NetworkType::MainTx => "https://api.mainnet-beta.solana.com",
             NetworkType::Serum => "https://solana-api.projectserum.com",
             NetworkType::Custom(nework_opts) => &nework_opts.url,
         }
@@ -33,7 +112,10 @@ impl NetworkType {
 }
 
 pub fn get_rpc_client(network: &NetworkType) -> ClientResult<RpcClient> {
-    let client = RpcClient::new(network.url().to_string());
+    let client = match network {
+        NetworkType::Custom(network_opts) => network_opts.rpc_client(),
+        _ => RpcClient::new(network.url().to_string()),
+    };
 
     let version = client.get_version()?;
     println!("RPC version: {:?}", version);
@@ -1,22 +1,13 @@
-use std::borrow::BorrowMut;
 use std::str::FromStr;
-use solana_client::rpc_client::RpcClient;
-use solana_program::account_info::{AccountInfo, next_account_info};
-use solana_program::clock::Epoch;
-use solana_program::entrypoint::ProgramResult;
+use solana_program::instruction::Instruction;
 use solana_program::pubkey::Pubkey;
-use solana_sdk::account::{Account, ReadableAccount};
-use solana_sdk::commitment_config::CommitmentConfig;
-use crate::node_client::NetworkType;
-use crate::raydium::state::{AmmInfo, AmmStatus};
-use crate::raydium::math::Calculator;
-use crate::raydium::instruction::{AmmInstruction, SwapInstructionBaseIn};
-use crate::raydium::error::AmmError;
-use crate::raydium::processor::Processor;
+use crate::account_store::AccountStore;
+use crate::node_client::NetworkOpts;
+use crate::raydium::client::RAYDIUM_AMM_PROGRAM_ID;
+use crate::raydium::instruction;
+use crate::snapshot::Snapshot;
 
-
-pub fn process_swap_base_in() -> ProgramResult {
-    let swap_base_in = AmmInstruction::SwapBaseIn(SwapInstructionBaseIn { amount_in: 1000000000, minimum_amount_out: 0 });
+fn swap_base_in_keys() -> Vec<Pubkey> {
     let mut keys: Vec<Pubkey> = vec![];
     ///   0. `[]` Spl Token program id
     ///   1. `[writable]` AMM Account
@@ -55,116 +46,56 @@ pub fn process_swap_base_in() -> ProgramResult {
     keys.push(Pubkey::from_str("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1").unwrap());
     keys.push(Pubkey::from_str("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1").unwrap());
 
-    let client = RpcClient::new(NetworkType::MainTx.url().to_string());
-    let accounts: Vec<Option<Account>> = client.get_multiple_accounts(&keys).unwrap();
-
-    let k0 = accounts.get(0).unwrap().clone().unwrap().owner.clone();
-    let l0 = &mut accounts.get(0).unwrap().clone().unwrap().lamports.clone();
-    let d0 = &mut accounts.get(0).unwrap().clone().unwrap().data.clone();
-    let e0 = accounts.get(0).unwrap().clone().unwrap().rent_epoch.clone();
-
-    let k1 = accounts.get(1).unwrap().clone().unwrap().owner.clone();
-    let l1 = &mut accounts.get(1).unwrap().clone().unwrap().lamports.clone();
-    let d1 = &mut accounts.get(1).unwrap().clone().unwrap().data.clone();
-    let e1 = accounts.get(1).unwrap().clone().unwrap().rent_epoch.clone();
-    println!("data_length={:?}",d1.len());
-
-    let k2 = accounts.get(2).unwrap().clone().unwrap().owner.clone();
-    let l2 = &mut accounts.get(2).unwrap().clone().unwrap().lamports.clone();
-    let d2 = &mut accounts.get(2).unwrap().clone().unwrap().data.clone();
-    let e2 = accounts.get(2).unwrap().clone().unwrap().rent_epoch.clone();
-
-    let k3 = accounts.get(3).unwrap().clone().unwrap().owner.clone();
-    let l3 = &mut accounts.get(3).unwrap().clone().unwrap().lamports.clone();
-    let d3 = &mut accounts.get(3).unwrap().clone().unwrap().data.clone();
-
-    let k4 = accounts.get(4).unwrap().clone().unwrap().owner.clone();
-    let l4 = &mut accounts.get(4).unwrap().clone().unwrap().lamports.clone();
-    let d4 = &mut accounts.get(4).unwrap().clone().unwrap().data.clone();
-
-    let k5 = accounts.get(5).unwrap().clone().unwrap().owner.clone();
-    let l5 = &mut accounts.get(5).unwrap().clone().unwrap().lamports.clone();
-    let d5 = &mut accounts.get(5).unwrap().clone().unwrap().data.clone();
-
-    let k6 = accounts.get(6).unwrap().clone().unwrap().owner.clone();
-    let l6 = &mut accounts.get(6).unwrap().clone().unwrap().lamports.clone();
-    let d6 = &mut accounts.get(6).unwrap().clone().unwrap().data.clone();
-
-    let k7 = accounts.get(7).unwrap().clone().unwrap().owner.clone();
-    let l7 = &mut accounts.get(7).unwrap().clone().unwrap().lamports.clone();
-    let d7 = &mut accounts.get(7).unwrap().clone().unwrap().data.clone();
-
-    let k8 = accounts.get(8).unwrap().clone().unwrap().owner.clone();
-    let l8 = &mut accounts.get(8).unwrap().clone().unwrap().lamports.clone();
-    let d8 = &mut accounts.get(8).unwrap().clone().unwrap().data.clone();
-
-    let k9 = accounts.get(9).unwrap().clone().unwrap().owner.clone();
-    let l9 = &mut accounts.get(9).unwrap().clone().unwrap().lamports.clone();
-    let d9 = &mut accounts.get(9).unwrap().clone().unwrap().data.clone();
-
-    let k10 = accounts.get(10).unwrap().clone().unwrap().owner.clone();
-    let l10 = &mut accounts.get(10).unwrap().clone().unwrap().lamports.clone();
-    let d10 = &mut accounts.get(10).unwrap().clone().unwrap().data.clone();
-
-    let k11 = accounts.get(11).unwrap().clone().unwrap().owner.clone();
-    let l11 = &mut accounts.get(11).unwrap().clone().unwrap().lamports.clone();
-    let d11 = &mut accounts.get(11).unwrap().clone().unwrap().data.clone();
-
-    let k12 = accounts.get(12).unwrap().clone().unwrap().owner.clone();
-    let l12 = &mut accounts.get(12).unwrap().clone().unwrap().lamports.clone();
-    let d12 = &mut accounts.get(12).unwrap().clone().unwrap().data.clone();
-
-    let k13 = accounts.get(13).unwrap().clone().unwrap().owner.clone();
-    let l13 = &mut accounts.get(13).unwrap().clone().unwrap().lamports.clone();
-    let d13 = &mut accounts.get(13).unwrap().clone().unwrap().data.clone();
-
-    let k14 = accounts.get(14).unwrap().clone().unwrap().owner.clone();
-    let l14 = &mut accounts.get(14).unwrap().clone().unwrap().lamports.clone();
-    let d14 = &mut accounts.get(14).unwrap().clone().unwrap().data.clone();
-
-    let k15 = accounts.get(15).unwrap().clone().unwrap().owner.clone();
-    let l15 = &mut accounts.get(15).unwrap().clone().unwrap().lamports.clone();
-    let d15 = &mut accounts.get(15).unwrap().clone().unwrap().data.clone();
+    keys
+}
 
-    let k16 = accounts.get(16).unwrap().clone().unwrap().owner.clone();
-    let l16 = &mut accounts.get(16).unwrap().clone().unwrap().lamports.clone();
-    let d16 = &mut accounts.get(16).unwrap().clone().unwrap().data.clone();
+fn swap_base_in_instruction(keys: &[Pubkey]) -> Instruction {
+    let program_id = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+    // keys[4] is the target orders account, which swaps no longer take
+    instruction::swap_base_in(
+        &program_id,
+        &keys[1],
+        &keys[2],
+        &keys[3],
+        &keys[5],
+        &keys[6],
+        &keys[7],
+        &keys[8],
+        &keys[9],
+        &keys[10],
+        &keys[11],
+        &keys[12],
+        &keys[13],
+        &keys[14],
+        &keys[15],
+        &keys[16],
+        &keys[17],
+        1000000000,
+        0,
+    )
+    .unwrap()
+}
 
-    let k17 = accounts.get(17).unwrap().clone().unwrap().owner.clone();
-    let l17 = &mut accounts.get(17).unwrap().clone().unwrap().lamports.clone();
-    let d17 = &mut accounts.get(17).unwrap().clone().unwrap().data.clone();
-    let infos = [
-        AccountInfo::new(&keys.get(0).unwrap(), false, false, l0, d0, &k0, false, e0),
-        AccountInfo::new(&keys.get(1).unwrap(), false, false, l1, d1, &k1, false, e1),
-        AccountInfo::new(&keys.get(2).unwrap(), false, false, l2, d2, &k2, false, e2),
-        AccountInfo::new(&keys.get(3).unwrap(), false, false, l3, d3, &k3, false, 0),
-        AccountInfo::new(&keys.get(4).unwrap(), false, false, l4, d4, &k4, false, 0),
-        AccountInfo::new(&keys.get(5).unwrap(), false, false, l5, d5, &k5, false, 0),
-        AccountInfo::new(&keys.get(6).unwrap(), false, false, l6, d6, &k6, false, 0),
-        AccountInfo::new(&keys.get(7).unwrap(), false, false, l7, d7, &k7, false, 0),
-        AccountInfo::new(&keys.get(8).unwrap(), false, false, l8, d8, &k8, false, 0),
-        AccountInfo::new(&keys.get(9).unwrap(), false, false, l9, d9, &k9, false, 0),
-        AccountInfo::new(&keys.get(10).unwrap(), false, false, l10, d10, &k10, false, 0),
-        AccountInfo::new(&keys.get(11).unwrap(), false, false, l11, d11, &k11, false, 0),
-        AccountInfo::new(&keys.get(12).unwrap(), false, false, l12, d12, &k12, false, 0),
-        AccountInfo::new(&keys.get(13).unwrap(), false, false, l13, d13, &k13, false, 0),
-        AccountInfo::new(&keys.get(14).unwrap(), false, false, l14, d14, &k14, false, 0),
-        AccountInfo::new(&keys.get(15).unwrap(), false, false, l15, d15, &k15, false, 0),
-        AccountInfo::new(&keys.get(16).unwrap(), false, false, l16, d16, &k16, false, 0),
-        AccountInfo::new(&keys.get(17).unwrap(), false, false, l17, d17, &k17, false, 0),
-    ];
+pub fn process_swap_base_in() -> anyhow::Result<()> {
+    let keys = swap_base_in_keys();
+    let client = NetworkOpts::from_env()?.rpc_client();
+    let mut store = AccountStore::new();
+    store.fetch(&client, &keys)?;
+    store.execute_raydium(&swap_base_in_instruction(&keys))?;
+    Ok(())
+}
 
-    let program_id = Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8").unwrap();
-    Processor::process(&program_id, &infos, swap_base_in.pack().unwrap().as_slice());
+/// Records the accounts of `process_swap_base_in` to `path`
+pub fn record_swap_base_in(path: &str) -> anyhow::Result<()> {
+    let client = NetworkOpts::from_env()?.rpc_client();
+    Snapshot::record(&client, &swap_base_in_keys())?.save(path)
+}
 
+/// Runs `process_swap_base_in` against a snapshot written by `record_swap_base_in`
+pub fn replay_swap_base_in(path: &str) -> anyhow::Result<()> {
+    let snapshot = Snapshot::load(path)?;
+    snapshot.install_clock();
+    let mut store = snapshot.account_store()?;
+    store.execute_raydium(&swap_base_in_instruction(&swap_base_in_keys()))?;
     Ok(())
 }
-
-pub fn convert_to_info<'a>(key: &'a Pubkey, account: &'a mut Account) -> AccountInfo<'a> {
-    AccountInfo::new(key,
-                     false, false,
-                     &mut account.lamports,
-                     &mut account.data,
-                     &account.owner, false,
-                     Epoch::default())
-}
\ No newline at end of file
@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::convert::identity;
+use std::str::FromStr;
+use crate::raydium::instruction;
+use crate::raydium::pool_keys::{KeyMismatch, RaydiumPoolKeys};
+use crate::raydium::processor::{Processor, AUTHORITY_AMM};
+use crate::raydium::state::{AmmInfo, AmmStatus};
+use anyhow::{anyhow, Result};
+use serum_dex::state::{gen_vault_signer_key, MarketState};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::RpcFilterType,
+};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address;
+use std::mem::size_of;
+
+pub const RAYDIUM_AMM_PROGRAM_ID: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+/// Serum account data starts with this padding, the market state follows it
+const SERUM_HEAD_PADDING: &[u8] = b"serum";
+
+pub fn fetch_all_amm_accounts(rpc_client: &RpcClient) -> Result<Vec<(Pubkey, AmmInfo)>> {
+    let amm_accounts = rpc_client.get_program_accounts_with_config(
+        &Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap(),
+        RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::DataSize(
+                size_of::<AmmInfo>() as u64
+            )]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )?;
+    // account data is not guaranteed to be aligned for AmmInfo, read a copy
+    let amm_accounts = amm_accounts
+        .into_iter()
+        .filter_map(|(pubkey, account)| {
+            bytemuck::try_pod_read_unaligned::<AmmInfo>(&account.data)
+                .ok()
+                .map(|amm| (pubkey, amm))
+        })
+        .collect::<Vec<(Pubkey, AmmInfo)>>();
+    Ok(amm_accounts)
+}
+
+pub fn fetch_live_amm_accounts(rpc_client: &RpcClient) -> Result<Vec<(Pubkey, AmmInfo)>> {
+    let amm_accounts = fetch_all_amm_accounts(rpc_client)?;
+    let live_amm_accounts = amm_accounts
+        .into_iter()
+        .filter(|(_, amm)| {
+            AmmStatus::valid_status(amm.status) && AmmStatus::from_u64(amm.status).swap_permission()
+        })
+        .collect::<Vec<(Pubkey, AmmInfo)>>();
+    Ok(live_amm_accounts)
+}
+
+/// Pools by mint pair, a pair finds its pools whichever mint is the coin
+#[derive(Default)]
+pub struct PoolIndex {
+    pools: HashMap<Pubkey, AmmInfo>,
+    by_pair: HashMap<(Pubkey, Pubkey), Vec<Pubkey>>,
+}
+
+impl PoolIndex {
+    pub fn new(pools: impl IntoIterator<Item = (Pubkey, AmmInfo)>) -> Self {
+        let mut index = PoolIndex::default();
+        for (pubkey, amm) in pools {
+            index.insert(pubkey, amm);
+        }
+        index
+    }
+
+    /// Index of the pools that currently allow swaps
+    pub fn fetch_live(rpc_client: &RpcClient) -> Result<Self> {
+        Ok(Self::new(fetch_live_amm_accounts(rpc_client)?))
+    }
+
+    fn pair_key(mint_a: &Pubkey, mint_b: &Pubkey) -> (Pubkey, Pubkey) {
+        if mint_a <= mint_b {
+            (*mint_a, *mint_b)
+        } else {
+            (*mint_b, *mint_a)
+        }
+    }
+
+    pub fn insert(&mut self, pubkey: Pubkey, amm: AmmInfo) {
+        let key = Self::pair_key(&amm.coin_vault_mint, &amm.pc_vault_mint);
+        if let Some(previous) = self.pools.insert(pubkey, amm) {
+            let previous_key = Self::pair_key(&previous.coin_vault_mint, &previous.pc_vault_mint);
+            if let Some(pools) = self.by_pair.get_mut(&previous_key) {
+                pools.retain(|pool| *pool != pubkey);
+            }
+        }
+        self.by_pair.entry(key).or_default().push(pubkey);
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&AmmInfo> {
+        self.pools.get(pubkey)
+    }
+
+    /// Pools trading `mint_a` against `mint_b`, in either order
+    pub fn pools_for_pair(&self, mint_a: &Pubkey, mint_b: &Pubkey) -> Vec<(Pubkey, &AmmInfo)> {
+        self.by_pair
+            .get(&Self::pair_key(mint_a, mint_b))
+            .map(|pools| {
+                pools
+                    .iter()
+                    .filter_map(|pubkey| self.pools.get(pubkey).map(|amm| (*pubkey, amm)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Every pair with at least one pool
+    pub fn pairs(&self) -> impl Iterator<Item = &(Pubkey, Pubkey)> {
+        self.by_pair.iter().filter(|(_, pools)| !pools.is_empty()).map(|(pair, _)| pair)
+    }
+
+    /// Pools whose stored keys don't match the derivation from their market
+    pub fn non_canonical(&self, program_id: &Pubkey) -> Vec<(Pubkey, Vec<KeyMismatch>)> {
+        self.pools
+            .iter()
+            .map(|(pubkey, amm)| (*pubkey, RaydiumPoolKeys::for_amm(program_id, amm).verify(pubkey, amm)))
+            .filter(|(_, mismatches)| !mismatches.is_empty())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+}
+
+/// Market state of an OpenBook/Serum market account
+pub fn decode_market_state(data: &[u8]) -> Result<MarketState> {
+    let start = SERUM_HEAD_PADDING.len();
+    let end = start + size_of::<MarketState>();
+    if data.len() < end || !data.starts_with(SERUM_HEAD_PADDING) {
+        return Err(anyhow!("not a serum market account"));
+    }
+    bytemuck::try_pod_read_unaligned::<MarketState>(&data[start..end])
+        .map_err(|e| anyhow!("market state: {}", e))
+}
+
+fn market_key(key: [u64; 4]) -> Pubkey {
+    Pubkey::new_from_array(bytemuck::cast(key))
+}
+
+/// Every account a swap against an amm pool needs, in the order `raydium::instruction` takes them
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapAccounts {
+    pub amm_program: Pubkey,
+    pub amm_pool: Pubkey,
+    pub amm_authority: Pubkey,
+    pub amm_open_orders: Pubkey,
+    pub amm_coin_vault: Pubkey,
+    pub amm_pc_vault: Pubkey,
+    pub market_program: Pubkey,
+    pub market: Pubkey,
+    pub market_bids: Pubkey,
+    pub market_asks: Pubkey,
+    pub market_event_queue: Pubkey,
+    pub market_coin_vault: Pubkey,
+    pub market_pc_vault: Pubkey,
+    pub market_vault_signer: Pubkey,
+    pub user_token_source: Pubkey,
+    pub user_token_destination: Pubkey,
+    pub user_source_owner: Pubkey,
+}
+
+impl SwapAccounts {
+    /// Resolves the accounts from already loaded pool and market state.
+    /// The user token accounts are the wallet's associated token accounts.
+    pub fn from_state(
+        amm_program: &Pubkey,
+        amm_pool: &Pubkey,
+        amm: &AmmInfo,
+        market: &MarketState,
+        wallet: &Pubkey,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+    ) -> Result<Self> {
+        let pool_mints = (amm.coin_vault_mint, amm.pc_vault_mint);
+        if pool_mints != (*input_mint, *output_mint) && pool_mints != (*output_mint, *input_mint) {
+            return Err(anyhow!(
+                "pool {} trades {}/{}, not {}/{}",
+                amm_pool, amm.coin_vault_mint, amm.pc_vault_mint, input_mint, output_mint
+            ));
+        }
+        if market_key(identity(market.own_address)) != amm.market {
+            return Err(anyhow!("market state is not pool {} market {}", amm_pool, amm.market));
+        }
+        let amm_authority = Processor::authority_id(amm_program, AUTHORITY_AMM, amm.nonce as u8)
+            .map_err(|e| anyhow!("pool {} authority: {:?}", amm_pool, e))?;
+        let market_vault_signer = gen_vault_signer_key(market.vault_signer_nonce, &amm.market, &amm.market_program)
+            .map_err(|e| anyhow!("market {} vault signer: {}", amm.market, e))?;
+        Ok(SwapAccounts {
+            amm_program: *amm_program,
+            amm_pool: *amm_pool,
+            amm_authority,
+            amm_open_orders: amm.open_orders,
+            amm_coin_vault: amm.coin_vault,
+            amm_pc_vault: amm.pc_vault,
+            market_program: amm.market_program,
+            market: amm.market,
+            market_bids: market_key(identity(market.bids)),
+            market_asks: market_key(identity(market.asks)),
+            market_event_queue: market_key(identity(market.event_q)),
+            market_coin_vault: market_key(identity(market.coin_vault)),
+            market_pc_vault: market_key(identity(market.pc_vault)),
+            market_vault_signer,
+            user_token_source: get_associated_token_address(wallet, input_mint),
+            user_token_destination: get_associated_token_address(wallet, output_mint),
+            user_source_owner: *wallet,
+        })
+    }
+
+    /// Loads the pool and its market to resolve the accounts of a swap from `input_mint` to `output_mint`
+    pub fn resolve(
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+        wallet: &Pubkey,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+    ) -> Result<Self> {
+        let amm_account = rpc_client.get_account(amm_pool)?;
+        let amm_program = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+        if amm_account.owner != amm_program {
+            return Err(anyhow!("{} is owned by {}, not the amm program {}", amm_pool, amm_account.owner, amm_program));
+        }
+        let amm = bytemuck::try_pod_read_unaligned::<AmmInfo>(&amm_account.data)
+            .map_err(|_| anyhow!("{} is not an amm pool", amm_pool))?;
+        let market_account = rpc_client.get_account(&amm.market)?;
+        if market_account.owner != amm.market_program {
+            return Err(anyhow!("market {} is not owned by {}", amm.market, amm.market_program));
+        }
+        let market = decode_market_state(&market_account.data)?;
+        Self::from_state(&amm_program, amm_pool, &amm, &market, wallet, input_mint, output_mint)
+    }
+
+    pub fn swap_base_in(&self, amount_in: u64, minimum_amount_out: u64) -> Result<Instruction> {
+        Ok(instruction::swap_base_in(
+            &self.amm_program,
+            &self.amm_pool,
+            &self.amm_authority,
+            &self.amm_open_orders,
+            &self.amm_coin_vault,
+            &self.amm_pc_vault,
+            &self.market_program,
+            &self.market,
+            &self.market_bids,
+            &self.market_asks,
+            &self.market_event_queue,
+            &self.market_coin_vault,
+            &self.market_pc_vault,
+            &self.market_vault_signer,
+            &self.user_token_source,
+            &self.user_token_destination,
+            &self.user_source_owner,
+            amount_in,
+            minimum_amount_out,
+        )?)
+    }
+
+    pub fn swap_base_out(&self, max_amount_in: u64, amount_out: u64) -> Result<Instruction> {
+        Ok(instruction::swap_base_out(
+            &self.amm_program,
+            &self.amm_pool,
+            &self.amm_authority,
+            &self.amm_open_orders,
+            &self.amm_coin_vault,
+            &self.amm_pc_vault,
+            &self.market_program,
+            &self.market,
+            &self.market_bids,
+            &self.market_asks,
+            &self.market_event_queue,
+            &self.market_coin_vault,
+            &self.market_pc_vault,
+            &self.market_vault_signer,
+            &self.user_token_source,
+            &self.user_token_destination,
+            &self.user_source_owner,
+            max_amount_in,
+            amount_out,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serum_dex::state::ToAlignedBytes;
+    use crate::raydium::processor::tests::PoolFixture;
+
+    fn amm(coin_mint: &Pubkey, pc_mint: &Pubkey) -> AmmInfo {
+        let mut amm = AmmInfo::default();
+        amm.coin_vault_mint = *coin_mint;
+        amm.pc_vault_mint = *pc_mint;
+        amm
+    }
+
+    fn pool_keys(pools: Vec<(Pubkey, &AmmInfo)>) -> Vec<Pubkey> {
+        let mut keys: Vec<Pubkey> = pools.into_iter().map(|(pubkey, _)| pubkey).collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn pools_for_pair_finds_both_mint_orders() {
+        let (sol, usdc, ray) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (sol_usdc, usdc_sol, ray_usdc) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let index = PoolIndex::new(vec![
+            (sol_usdc, amm(&sol, &usdc)),
+            (usdc_sol, amm(&usdc, &sol)),
+            (ray_usdc, amm(&ray, &usdc)),
+        ]);
+
+        let mut expected = vec![sol_usdc, usdc_sol];
+        expected.sort();
+        assert_eq!(pool_keys(index.pools_for_pair(&sol, &usdc)), expected);
+        assert_eq!(pool_keys(index.pools_for_pair(&usdc, &sol)), expected);
+        assert_eq!(pool_keys(index.pools_for_pair(&usdc, &ray)), vec![ray_usdc]);
+        assert!(index.pools_for_pair(&sol, &ray).is_empty());
+        assert_eq!(index.pairs().count(), 2);
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn reinserting_a_pool_moves_it_to_its_new_pair() {
+        let (sol, usdc, ray) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let pool = Pubkey::new_unique();
+        let mut index = PoolIndex::new(vec![(pool, amm(&sol, &usdc))]);
+
+        index.insert(pool, amm(&ray, &usdc));
+        assert!(index.pools_for_pair(&sol, &usdc).is_empty());
+        assert_eq!(pool_keys(index.pools_for_pair(&usdc, &ray)), vec![pool]);
+        assert_eq!(index.pairs().collect::<Vec<_>>(), vec![&PoolIndex::pair_key(&ray, &usdc)]);
+        assert_eq!(index.get(&pool).unwrap().coin_vault_mint, ray);
+
+        // same mints again, the pool is listed once
+        index.insert(pool, amm(&usdc, &ray));
+        assert_eq!(pool_keys(index.pools_for_pair(&ray, &usdc)), vec![pool]);
+        assert_eq!(index.len(), 1);
+    }
+
+    fn from_fixture(
+        fixture: &PoolFixture,
+        market: &MarketState,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+    ) -> Result<SwapAccounts> {
+        SwapAccounts::from_state(
+            &Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap(),
+            &fixture.keys.amm_id,
+            &fixture.amm(),
+            market,
+            &fixture.user,
+            input_mint,
+            output_mint,
+        )
+    }
+
+    #[test]
+    fn from_state_resolves_accounts_in_instruction_order() {
+        let fixture = PoolFixture::new();
+        let amm = fixture.amm();
+        let keys = &fixture.keys;
+        let accounts = from_fixture(&fixture, &fixture.market_state(), &amm.pc_vault_mint, &amm.coin_vault_mint).unwrap();
+        let user_pc = get_associated_token_address(&fixture.user, &amm.pc_vault_mint);
+        let user_coin = get_associated_token_address(&fixture.user, &amm.coin_vault_mint);
+
+        let expected = vec![
+            keys.amm_id,
+            keys.authority,
+            keys.open_orders,
+            keys.coin_vault,
+            keys.pc_vault,
+            fixture.market_program,
+            keys.market,
+            fixture.bids,
+            fixture.asks,
+            fixture.event_queue,
+            fixture.market_coin_vault,
+            fixture.market_pc_vault,
+            fixture.vault_signer,
+            user_pc,
+            user_coin,
+            fixture.user,
+        ];
+        for ix in [accounts.swap_base_in(1, 0).unwrap(), accounts.swap_base_out(1, 1).unwrap()] {
+            assert_eq!(ix.program_id, keys.program_id);
+            let metas: Vec<Pubkey> = ix.accounts.iter().map(|meta| meta.pubkey).collect();
+            assert_eq!(metas[0], spl_token::id());
+            assert_eq!(metas[1..], expected[..]);
+        }
+
+        // the other direction only swaps the user token accounts
+        let reversed = from_fixture(&fixture, &fixture.market_state(), &amm.coin_vault_mint, &amm.pc_vault_mint).unwrap();
+        assert_eq!(
+            reversed,
+            SwapAccounts {
+                user_token_source: user_coin,
+                user_token_destination: user_pc,
+                ..accounts
+            }
+        );
+    }
+
+    #[test]
+    fn from_state_rejects_other_mints_and_markets() {
+        let fixture = PoolFixture::new();
+        let amm = fixture.amm();
+        let market = fixture.market_state();
+
+        let other_mint = Pubkey::new_unique();
+        let err = from_fixture(&fixture, &market, &amm.coin_vault_mint, &other_mint).unwrap_err();
+        assert!(err.to_string().contains("trades"), "{}", err);
+        let err = from_fixture(&fixture, &market, &amm.coin_vault_mint, &amm.coin_vault_mint).unwrap_err();
+        assert!(err.to_string().contains("trades"), "{}", err);
+
+        let mut other_market = market;
+        other_market.own_address = Pubkey::new_unique().to_aligned_bytes();
+        let err = from_fixture(&fixture, &other_market, &amm.coin_vault_mint, &amm.pc_vault_mint).unwrap_err();
+        assert!(err.to_string().contains("market state is not"), "{}", err);
+    }
+}
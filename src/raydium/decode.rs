@@ -0,0 +1,362 @@
+//! Amm instructions of confirmed transactions, top level and inner, with named accounts
+
+use std::str::FromStr;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, UiInnerInstructions, UiInstruction, UiLoadedAddresses,
+    UiTransactionEncoding,
+};
+use crate::error::{Error, Result};
+use crate::raydium::instruction::AmmInstruction;
+
+const INITIALIZE2_ACCOUNTS: &[&str] = &[
+    "token_program", "associated_token_program", "system_program", "rent", "amm_pool", "amm_authority",
+    "amm_open_orders", "amm_lp_mint", "amm_coin_mint", "amm_pc_mint", "amm_coin_vault", "amm_pc_vault",
+    "amm_target_orders", "amm_config", "create_pool_fee", "market_program", "market", "user_wallet",
+    "user_token_coin", "user_token_pc", "user_token_lp",
+];
+const MONITOR_STEP_ACCOUNTS: &[&str] = &[
+    "token_program", "rent", "clock", "amm_pool", "amm_authority", "amm_open_orders", "amm_target_orders",
+    "amm_coin_vault", "amm_pc_vault", "market_program", "market", "market_coin_vault", "market_pc_vault",
+    "market_vault_signer", "market_request_queue", "market_event_queue", "market_bids", "market_asks",
+    "srm_token", "referrer_pc_wallet",
+];
+const DEPOSIT_ACCOUNTS: &[&str] = &[
+    "token_program", "amm_pool", "amm_authority", "amm_open_orders", "amm_target_orders", "amm_lp_mint",
+    "amm_coin_vault", "amm_pc_vault", "market", "user_token_coin", "user_token_pc", "user_token_lp",
+    "user_owner", "market_event_queue",
+];
+const WITHDRAW_ACCOUNTS: &[&str] = &[
+    "token_program", "amm_pool", "amm_authority", "amm_open_orders", "amm_target_orders", "amm_lp_mint",
+    "amm_coin_vault", "amm_pc_vault", "market_program", "market", "market_coin_vault", "market_pc_vault",
+    "market_vault_signer", "user_token_lp", "user_token_coin", "user_token_pc", "user_owner",
+    "market_event_queue", "market_bids", "market_asks",
+];
+const MIGRATE_TO_OPEN_BOOK_ACCOUNTS: &[&str] = &[
+    "token_program", "system_program", "rent", "amm_pool", "amm_authority", "amm_open_orders",
+    "amm_coin_vault", "amm_pc_vault", "amm_target_orders", "market_program", "market", "market_bids",
+    "market_asks", "market_event_queue", "market_coin_vault", "market_pc_vault", "market_vault_signer",
+    "new_amm_open_orders", "new_market_program", "new_market", "admin",
+];
+const SET_PARAMS_ACCOUNTS: &[&str] = &[
+    "token_program", "amm_pool", "amm_authority", "amm_open_orders", "amm_target_orders", "amm_coin_vault",
+    "amm_pc_vault", "market_program", "market", "market_coin_vault", "market_pc_vault", "market_vault_signer",
+    "market_event_queue", "market_bids", "market_asks", "admin", "new_amm_open_orders",
+];
+const WITHDRAW_PNL_ACCOUNTS: &[&str] = &[
+    "token_program", "amm_pool", "amm_config", "amm_authority", "amm_open_orders", "amm_coin_vault",
+    "amm_pc_vault", "user_token_coin", "user_token_pc", "user_owner", "amm_target_orders", "market_program",
+    "market", "market_event_queue", "market_coin_vault", "market_pc_vault", "market_vault_signer",
+    "referrer_pc_wallet",
+];
+const WITHDRAW_SRM_ACCOUNTS: &[&str] = &[
+    "token_program", "amm_pool", "amm_owner", "amm_authority", "srm_token", "dest_srm_token",
+];
+const SWAP_ACCOUNTS: &[&str] = &[
+    "token_program", "amm_pool", "amm_authority", "amm_open_orders", "amm_target_orders", "amm_coin_vault",
+    "amm_pc_vault", "market_program", "market", "market_bids", "market_asks", "market_event_queue",
+    "market_coin_vault", "market_pc_vault", "market_vault_signer", "user_token_source",
+    "user_token_destination", "user_source_owner",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NamedAccount {
+    /// position in the instruction's accounts
+    pub index: usize,
+    /// from the instruction docs, `None` where they name none
+    pub name: Option<&'static str>,
+    pub pubkey: Pubkey,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedInstruction {
+    /// top level instruction, for an inner one the instruction that invoked it
+    pub outer_index: usize,
+    /// position among the inner instructions of `outer_index`, `None` at the top level
+    pub inner_index: Option<usize>,
+    pub instruction: AmmInstruction,
+    pub accounts: Vec<NamedAccount>,
+}
+
+impl DecodedInstruction {
+    pub fn account(&self, name: &str) -> Option<Pubkey> {
+        self.accounts.iter().find(|account| account.name == Some(name)).map(|account| account.pubkey)
+    }
+}
+
+/// Account names of `instruction` when it is passed `count` accounts
+#[allow(deprecated)]
+fn account_names(instruction: &AmmInstruction, count: usize) -> Vec<&'static str> {
+    let names: &[&str] = match instruction {
+        AmmInstruction::Initialize2(_) => INITIALIZE2_ACCOUNTS,
+        AmmInstruction::MonitorStep(_) => MONITOR_STEP_ACCOUNTS,
+        AmmInstruction::Deposit(_) => DEPOSIT_ACCOUNTS,
+        AmmInstruction::Withdraw(_) => WITHDRAW_ACCOUNTS,
+        AmmInstruction::MigrateToOpenBook => MIGRATE_TO_OPEN_BOOK_ACCOUNTS,
+        AmmInstruction::SetParams(_) => SET_PARAMS_ACCOUNTS,
+        AmmInstruction::WithdrawPnl => WITHDRAW_PNL_ACCOUNTS,
+        AmmInstruction::WithdrawSrm(_) => WITHDRAW_SRM_ACCOUNTS,
+        AmmInstruction::SwapBaseIn(_) | AmmInstruction::SwapBaseOut(_) => SWAP_ACCOUNTS,
+        _ => &[],
+    };
+    let mut names = names.to_vec();
+    let is_swap = matches!(instruction, AmmInstruction::SwapBaseIn(_) | AmmInstruction::SwapBaseOut(_));
+    if is_swap && count == SWAP_ACCOUNTS.len() - 1 {
+        // swaps may leave out the unused target orders account
+        names.retain(|name| *name != "amm_target_orders");
+    }
+    names
+}
+
+fn name_accounts(instruction: &AmmInstruction, keys: Vec<Pubkey>) -> Vec<NamedAccount> {
+    let names = account_names(instruction, keys.len());
+    keys.into_iter()
+        .enumerate()
+        .map(|(index, pubkey)| NamedAccount {
+            index,
+            name: names.get(index).copied(),
+            pubkey,
+        })
+        .collect()
+}
+
+fn parse_keys(keys: &[String]) -> Result<Vec<Pubkey>> {
+    keys.iter().map(|key| Ok(Pubkey::from_str(key)?)).collect()
+}
+
+/// Static keys followed by the addresses loaded from lookup tables, writable then readonly
+pub fn account_keys(tx: &EncodedConfirmedTransactionWithStatusMeta) -> Result<Vec<Pubkey>> {
+    let decoded = tx
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| Error::Decode("transaction must be fetched with a binary encoding".to_string()))?;
+    let mut keys = decoded.message.static_account_keys().to_vec();
+    if let Some(meta) = &tx.transaction.meta {
+        if let OptionSerializer::Some(UiLoadedAddresses { writable, readonly }) = &meta.loaded_addresses {
+            keys.extend(parse_keys(writable)?);
+            keys.extend(parse_keys(readonly)?);
+        }
+    }
+    Ok(keys)
+}
+
+/// An instruction of a confirmed transaction with its accounts resolved
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolvedInstruction {
+    /// top level instruction, for an inner one the instruction that invoked it
+    pub outer_index: usize,
+    /// position among the inner instructions of `outer_index`, `None` at the top level
+    pub inner_index: Option<usize>,
+    pub program_id: Pubkey,
+    pub accounts: Vec<Pubkey>,
+    pub data: Vec<u8>,
+}
+
+/// Every instruction of `tx`, top level and inner, in execution order
+pub fn resolve_instructions(tx: &EncodedConfirmedTransactionWithStatusMeta) -> Result<Vec<ResolvedInstruction>> {
+    let decoded = tx
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| Error::Decode("transaction must be fetched with a binary encoding".to_string()))?;
+    let keys = account_keys(tx)?;
+    let key = |index: usize| {
+        keys.get(index)
+            .copied()
+            .ok_or_else(|| Error::Decode(format!("account index {} out of range", index)))
+    };
+    let inner: Vec<UiInnerInstructions> = match tx.transaction.meta.as_ref().map(|meta| &meta.inner_instructions) {
+        Some(OptionSerializer::Some(inner)) => inner.clone(),
+        _ => vec![],
+    };
+
+    let mut instructions = Vec::new();
+    for (outer_index, ix) in decoded.message.instructions().iter().enumerate() {
+        instructions.push(ResolvedInstruction {
+            outer_index,
+            inner_index: None,
+            program_id: key(ix.program_id_index as usize)?,
+            accounts: ix.accounts.iter().map(|index| key(*index as usize)).collect::<Result<Vec<_>>>()?,
+            data: ix.data.clone(),
+        });
+        for inner_ixs in inner.iter().filter(|inner_ixs| inner_ixs.index as usize == outer_index) {
+            for (inner_index, inner_ix) in inner_ixs.instructions.iter().enumerate() {
+                let compiled = match inner_ix {
+                    UiInstruction::Compiled(compiled) => compiled,
+                    UiInstruction::Parsed(_) => {
+                        return Err(Error::Decode("inner instructions must not be json parsed".to_string()))
+                    }
+                };
+                let data = bs58::decode(&compiled.data)
+                    .into_vec()
+                    .map_err(|e| Error::Decode(format!("inner instruction data: {}", e)))?;
+                instructions.push(ResolvedInstruction {
+                    outer_index,
+                    inner_index: Some(inner_index),
+                    program_id: key(compiled.program_id_index as usize)?,
+                    accounts: compiled
+                        .accounts
+                        .iter()
+                        .map(|index| key(*index as usize))
+                        .collect::<Result<Vec<_>>>()?,
+                    data,
+                });
+            }
+        }
+    }
+    Ok(instructions)
+}
+
+/// Every instruction to `program_id` in `tx`, in execution order.
+/// Instructions whose data doesn't unpack are skipped. Instructions of failed transactions
+/// decode like any other, check `meta.err` before taking them as executed.
+pub fn decode_transaction(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    program_id: &Pubkey,
+) -> Result<Vec<DecodedInstruction>> {
+    Ok(resolve_instructions(tx)?
+        .into_iter()
+        .filter(|ix| ix.program_id == *program_id)
+        .filter_map(|ix| {
+            let instruction = AmmInstruction::unpack(&ix.data).ok()?;
+            Some(DecodedInstruction {
+                outer_index: ix.outer_index,
+                inner_index: ix.inner_index,
+                accounts: name_accounts(&instruction, ix.accounts),
+                instruction,
+            })
+        })
+        .collect())
+}
+
+/// Confirmed transaction in the encoding the decoders here need, v0 included
+pub fn fetch_transaction(client: &RpcClient, signature: &Signature) -> Result<EncodedConfirmedTransactionWithStatusMeta> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        // getTransaction serves confirmed and finalized only
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    Ok(client.get_transaction_with_config(signature, config)?)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use solana_sdk::instruction::{AccountMeta, Instruction};
+    use solana_sdk::message::{Message, VersionedMessage};
+    use solana_sdk::transaction::VersionedTransaction;
+    use solana_transaction_status::{EncodedTransaction, EncodedTransactionWithStatusMeta, TransactionBinaryEncoding};
+    use crate::raydium::client::RAYDIUM_AMM_PROGRAM_ID;
+    use crate::raydium::instruction::{self, SwapInstructionBaseIn};
+
+    /// Swap base in of 1000 with a minimum of 990 out, fresh keys for the accounts not passed
+    pub(crate) fn swap(amm_pool: &Pubkey, user_source_owner: &Pubkey) -> Instruction {
+        let program_id = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+        let keys: Vec<Pubkey> = (0..13).map(|_| Pubkey::new_unique()).collect();
+        instruction::swap_base_in(
+            &program_id, amm_pool, &keys[0], &keys[1], &keys[2], &keys[3], &keys[4], &keys[5], &keys[6],
+            &keys[7], &keys[8], &keys[9], &keys[10], &keys[11], &keys[12], &Pubkey::new_unique(),
+            user_source_owner, 1_000, 990,
+        )
+        .unwrap()
+    }
+
+    pub(crate) fn confirmed(message: Message, meta: Value) -> EncodedConfirmedTransactionWithStatusMeta {
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+            message: VersionedMessage::Legacy(message),
+        };
+        EncodedConfirmedTransactionWithStatusMeta {
+            slot: 1,
+            transaction: EncodedTransactionWithStatusMeta {
+                transaction: EncodedTransaction::Binary(
+                    base64::encode(bincode::serialize(&tx).unwrap()),
+                    TransactionBinaryEncoding::Base64,
+                ),
+                meta: Some(serde_json::from_value(meta).unwrap()),
+                version: None,
+            },
+            block_time: None,
+        }
+    }
+
+    fn meta(err: Value, inner_instructions: Value) -> Value {
+        let status = if err.is_null() { json!({ "Ok": null }) } else { json!({ "Err": err.clone() }) };
+        json!({
+            "err": err,
+            "status": status,
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "innerInstructions": inner_instructions,
+        })
+    }
+
+    #[test]
+    fn names_swap_accounts_and_keeps_unnamed_ones() {
+        let program_id = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+        let (amm_pool, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = swap(&amm_pool, &owner);
+        let tx = confirmed(Message::new(&[ix.clone()], Some(&owner)), meta(Value::Null, json!([])));
+
+        let decoded = decode_transaction(&tx, &program_id).unwrap();
+        assert_eq!(decoded.len(), 1);
+        let swap = &decoded[0];
+        assert_eq!((swap.outer_index, swap.inner_index), (0, None));
+        assert_eq!(
+            swap.instruction,
+            AmmInstruction::SwapBaseIn(SwapInstructionBaseIn { amount_in: 1_000, minimum_amount_out: 990 })
+        );
+        // the target orders account is left out, the names after it move up
+        assert_eq!(swap.account("amm_target_orders"), None);
+        assert_eq!(swap.account("amm_pool"), Some(amm_pool));
+        assert_eq!(swap.account("user_source_owner"), Some(owner));
+        assert_eq!(swap.accounts[4].name, Some("amm_coin_vault"));
+        assert_eq!(swap.accounts[4].pubkey, ix.accounts[4].pubkey);
+
+        let mut long = ix;
+        long.accounts.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+        long.accounts.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+        let extra = long.accounts[18].pubkey;
+        let tx = confirmed(Message::new(&[long], Some(&owner)), meta(Value::Null, json!([])));
+        let decoded = decode_transaction(&tx, &program_id).unwrap();
+        let last = decoded[0].accounts[18];
+        assert_eq!((last.index, last.name, last.pubkey), (18, None, extra));
+    }
+
+    #[test]
+    fn decodes_inner_swaps_of_failed_transactions() {
+        let program_id = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+        let (amm_pool, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = swap(&amm_pool, &owner);
+        let mut router_accounts = ix.accounts.clone();
+        router_accounts.push(AccountMeta::new_readonly(program_id, false));
+        let router = Instruction::new_with_bytes(Pubkey::new_unique(), &[7], router_accounts);
+        let message = Message::new(&[router], Some(&owner));
+        let index = |key: &Pubkey| message.account_keys.iter().position(|k| k == key).unwrap();
+        let inner = json!([{
+            "index": 0,
+            "instructions": [{
+                "programIdIndex": index(&program_id),
+                "accounts": ix.accounts.iter().map(|meta| index(&meta.pubkey)).collect::<Vec<usize>>(),
+                "data": bs58::encode(&ix.data).into_string(),
+            }],
+        }]);
+        let err = json!({ "InstructionError": [0, { "Custom": 30 }] });
+        let tx = confirmed(message, meta(err, inner));
+
+        assert!(tx.transaction.meta.as_ref().unwrap().err.is_some());
+        let decoded = decode_transaction(&tx, &program_id).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!((decoded[0].outer_index, decoded[0].inner_index), (0, Some(0)));
+        assert_eq!(decoded[0].account("amm_pool"), Some(amm_pool));
+    }
+}
@@ -2,4 +2,8 @@ pub mod instruction;
 pub mod math;
 pub mod state;
 pub mod processor;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod quote;
+pub mod client;
+pub mod pool_keys;
+pub mod decode;
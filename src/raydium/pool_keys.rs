@@ -0,0 +1,132 @@
+//! Program derived addresses of an amm pool, from its program and market
+
+use solana_program::pubkey::Pubkey;
+use crate::raydium::processor::{
+    get_associated_address_and_bump_seed, AMM_ASSOCIATED_SEED, AMM_CONFIG_SEED, AUTHORITY_AMM,
+    COIN_VAULT_ASSOCIATED_SEED, LP_MINT_ASSOCIATED_SEED, OPEN_ORDER_ASSOCIATED_SEED,
+    PC_VAULT_ASSOCIATED_SEED, TARGET_ASSOCIATED_SEED,
+};
+use crate::raydium::state::AmmInfo;
+
+/// Canonical keys of the pool the program creates for `market`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RaydiumPoolKeys {
+    pub program_id: Pubkey,
+    pub market: Pubkey,
+    pub amm_id: Pubkey,
+    pub authority: Pubkey,
+    /// bump of `authority`, stored as `AmmInfo::nonce`
+    pub nonce: u8,
+    pub open_orders: Pubkey,
+    pub target_orders: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub lp_mint: Pubkey,
+    /// program wide, the same for every pool
+    pub amm_config: Pubkey,
+}
+
+/// A stored key that differs from its canonical derivation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyMismatch {
+    pub field: &'static str,
+    pub expected: Pubkey,
+    pub actual: Pubkey,
+}
+
+impl RaydiumPoolKeys {
+    pub fn derive(program_id: &Pubkey, market: &Pubkey) -> Self {
+        let associated = |seed: &[u8]| get_associated_address_and_bump_seed(program_id, market, seed, program_id).0;
+        let (authority, nonce) = Pubkey::find_program_address(&[AUTHORITY_AMM], program_id);
+        let (amm_config, _) = Pubkey::find_program_address(&[AMM_CONFIG_SEED], program_id);
+        RaydiumPoolKeys {
+            program_id: *program_id,
+            market: *market,
+            amm_id: associated(AMM_ASSOCIATED_SEED),
+            authority,
+            nonce,
+            open_orders: associated(OPEN_ORDER_ASSOCIATED_SEED),
+            target_orders: associated(TARGET_ASSOCIATED_SEED),
+            coin_vault: associated(COIN_VAULT_ASSOCIATED_SEED),
+            pc_vault: associated(PC_VAULT_ASSOCIATED_SEED),
+            lp_mint: associated(LP_MINT_ASSOCIATED_SEED),
+            amm_config,
+        }
+    }
+
+    /// Keys derived from the market `amm` stores
+    pub fn for_amm(program_id: &Pubkey, amm: &AmmInfo) -> Self {
+        Self::derive(program_id, &amm.market)
+    }
+
+    /// Every key of the pool at `amm_id` that isn't the canonical one, empty for a canonical pool
+    pub fn verify(&self, amm_id: &Pubkey, amm: &AmmInfo) -> Vec<KeyMismatch> {
+        let mut mismatches = Vec::new();
+        let mut check = |field: &'static str, expected: Pubkey, actual: Pubkey| {
+            if expected != actual {
+                mismatches.push(KeyMismatch { field, expected, actual });
+            }
+        };
+        check("market", self.market, amm.market);
+        check("amm_id", self.amm_id, *amm_id);
+        check("open_orders", self.open_orders, amm.open_orders);
+        check("target_orders", self.target_orders, amm.target_orders);
+        check("coin_vault", self.coin_vault, amm.coin_vault);
+        check("pc_vault", self.pc_vault, amm.pc_vault);
+        check("lp_mint", self.lp_mint, amm.lp_mint);
+        // any bump the program accepts works, report what the stored one derives to
+        let authority = Pubkey::create_program_address(&[AUTHORITY_AMM, &[amm.nonce as u8]], &self.program_id)
+            .unwrap_or_default();
+        check("authority", self.authority, authority);
+        mismatches
+    }
+
+    pub fn is_canonical(&self, amm_id: &Pubkey, amm: &AmmInfo) -> bool {
+        self.verify(amm_id, amm).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raydium::processor::tests::PoolFixture;
+
+    #[test]
+    fn fixture_pool_is_canonical() {
+        let fixture = PoolFixture::new();
+        let amm = fixture.amm();
+        let keys = RaydiumPoolKeys::for_amm(&fixture.keys.program_id, &amm);
+        assert_eq!(keys, fixture.keys);
+        assert_eq!(keys.verify(&keys.amm_id, &amm), vec![]);
+        assert!(keys.is_canonical(&keys.amm_id, &amm));
+    }
+
+    #[test]
+    fn tampered_keys_are_reported() {
+        let fixture = PoolFixture::new();
+        let keys = &fixture.keys;
+        let mut amm = fixture.amm();
+        let coin_vault = Pubkey::new_unique();
+        amm.coin_vault = coin_vault;
+        assert_eq!(
+            keys.verify(&keys.amm_id, &amm),
+            vec![KeyMismatch {
+                field: "coin_vault",
+                expected: keys.coin_vault,
+                actual: coin_vault,
+            }]
+        );
+        assert!(!keys.is_canonical(&keys.amm_id, &amm));
+
+        let amm = fixture.amm();
+        let amm_id = Pubkey::new_unique();
+        assert_eq!(
+            keys.verify(&amm_id, &amm),
+            vec![KeyMismatch {
+                field: "amm_id",
+                expected: keys.amm_id,
+                actual: amm_id,
+            }]
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use serum_dex::{
     critbit::{LeafNode, Slab, SlabView},
     matching::{OrderType, Side},
-    state::{Market, MarketState, OpenOrders, ToAlignedBytes},
+    state::{gen_vault_signer_key, Market, MarketState, OpenOrders, ToAlignedBytes},
 };
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
@@ -27,9 +27,17 @@ use std::{
     num::NonZeroU64, ops::Deref,
 };
 use crate::raydium::error::AmmError;
-use crate::raydium::instruction::{AmmInstruction, InitializeInstruction2, SwapInstructionBaseIn};
-use crate::raydium::math::{U128, Calculator, InvariantPool, RoundDirection, SwapDirection, U256, CheckedCeilDiv};
-use crate::raydium::state::{AmmConfig, AmmInfo, AmmStatus, TargetOrders};
+use crate::raydium::instruction::{
+    AmmInstruction, DepositInstruction, InitializeInstruction2, SimulateInstruction,
+    SwapInstructionBaseIn, SwapInstructionBaseOut, WithdrawInstruction,
+};
+use crate::raydium::math::{U128, Calculator, InvariantPool, InvariantToken, RoundDirection, SwapDirection, U256, CheckedCeilDiv};
+use crate::raydium::quote;
+use crate::raydium::state::{
+    AmmConfig, AmmInfo, AmmState, AmmStatus, GetPoolData, GetSwapBaseInData, GetSwapBaseOutData,
+    RunCrankData, SimulateParams, TargetOrders,
+};
+use spl_token::error::TokenError;
 
 pub mod srm_token {
     solana_program::declare_id!("SRMuApVNdxXokk5GT7XD5cUUgXMBCoAz2LHeuAoKWRt");
@@ -129,6 +137,350 @@ pub fn get_associated_address_and_bump_seed(
     )
 }
 
+/// Swap executed by the local processor, with the token balances it left behind
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapResult {
+    pub swap_direction: SwapDirection,
+    /// source token paid by the user, swap fee included
+    pub amount_in: u64,
+    /// destination token received by the user
+    pub amount_out: u64,
+    /// swap fee charged in source token
+    pub swap_fee: u64,
+    /// user source token account balance after the swap
+    pub user_source_amount: u64,
+    /// user destination token account balance after the swap
+    pub user_destination_amount: u64,
+    /// amm coin vault balance after the swap
+    pub amm_coin_vault_amount: u64,
+    /// amm pc vault balance after the swap
+    pub amm_pc_vault_amount: u64,
+}
+
+/// Token program and dex effects of the amm instructions.
+/// There is no runtime to take the cross program invocations when the processor runs
+/// locally, so their effects are applied to the token, market and open orders data directly.
+pub struct Invokers {}
+
+impl Invokers {
+    fn check_token_accounts(token_program: &AccountInfo, accounts: &[&AccountInfo]) -> ProgramResult {
+        if *token_program.key != spl_token::id() {
+            return Err(AmmError::InvalidSplTokenProgram.into());
+        }
+        for account in accounts {
+            if account.owner != token_program.key {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_authority(program_id: &Pubkey, authority: &AccountInfo, nonce: u8) -> ProgramResult {
+        if *authority.key != Processor::authority_id(program_id, AUTHORITY_AMM, nonce)? {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        Ok(())
+    }
+
+    fn transfer(
+        source: &AccountInfo,
+        destination: &AccountInfo,
+        authority: &AccountInfo,
+        amount: u64,
+    ) -> ProgramResult {
+        let mut source_account = spl_token::state::Account::unpack(&source.data.borrow())?;
+        let mut destination_account =
+            spl_token::state::Account::unpack(&destination.data.borrow())?;
+        if source_account.mint != destination_account.mint {
+            return Err(TokenError::MintMismatch.into());
+        }
+        if source_account.owner != *authority.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if source_account.is_frozen() || destination_account.is_frozen() {
+            return Err(TokenError::AccountFrozen.into());
+        }
+        source_account.amount = source_account
+            .amount
+            .checked_sub(amount)
+            .ok_or(TokenError::InsufficientFunds)?;
+        if source.key == destination.key {
+            return Ok(());
+        }
+        destination_account.amount = destination_account
+            .amount
+            .checked_add(amount)
+            .ok_or(TokenError::Overflow)?;
+        spl_token::state::Account::pack(source_account, &mut source.data.borrow_mut())?;
+        spl_token::state::Account::pack(destination_account, &mut destination.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Transfer signed by the user wallet
+    pub fn token_transfer(
+        token_program: &AccountInfo,
+        source: &AccountInfo,
+        destination: &AccountInfo,
+        authority: &AccountInfo,
+        amount: u64,
+    ) -> ProgramResult {
+        Self::check_token_accounts(token_program, &[source, destination])?;
+        if !authority.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Self::transfer(source, destination, authority, amount)
+    }
+
+    /// Transfer signed by the amm authority
+    pub fn token_transfer_with_authority(
+        program_id: &Pubkey,
+        token_program: &AccountInfo,
+        source: &AccountInfo,
+        destination: &AccountInfo,
+        authority: &AccountInfo,
+        nonce: u8,
+        amount: u64,
+    ) -> ProgramResult {
+        Self::check_token_accounts(token_program, &[source, destination])?;
+        Self::check_authority(program_id, authority, nonce)?;
+        Self::transfer(source, destination, authority, amount)
+    }
+
+    /// Mint lp signed by the amm authority
+    pub fn token_mint_to(
+        program_id: &Pubkey,
+        token_program: &AccountInfo,
+        mint: &AccountInfo,
+        destination: &AccountInfo,
+        authority: &AccountInfo,
+        nonce: u8,
+        amount: u64,
+    ) -> ProgramResult {
+        Self::check_token_accounts(token_program, &[mint, destination])?;
+        Self::check_authority(program_id, authority, nonce)?;
+        let mut mint_state = spl_token::state::Mint::unpack(&mint.data.borrow())?;
+        let mut destination_account =
+            spl_token::state::Account::unpack(&destination.data.borrow())?;
+        if destination_account.mint != *mint.key {
+            return Err(TokenError::MintMismatch.into());
+        }
+        if destination_account.is_frozen() {
+            return Err(TokenError::AccountFrozen.into());
+        }
+        match mint_state.mint_authority {
+            COption::Some(mint_authority) if mint_authority == *authority.key => {}
+            COption::Some(_) => return Err(TokenError::OwnerMismatch.into()),
+            COption::None => return Err(TokenError::FixedSupply.into()),
+        }
+        mint_state.supply = mint_state
+            .supply
+            .checked_add(amount)
+            .ok_or(TokenError::Overflow)?;
+        destination_account.amount = destination_account
+            .amount
+            .checked_add(amount)
+            .ok_or(TokenError::Overflow)?;
+        spl_token::state::Mint::pack(mint_state, &mut mint.data.borrow_mut())?;
+        spl_token::state::Account::pack(destination_account, &mut destination.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Burn lp signed by the user wallet
+    pub fn token_burn(
+        token_program: &AccountInfo,
+        burn_account: &AccountInfo,
+        mint: &AccountInfo,
+        owner: &AccountInfo,
+        amount: u64,
+    ) -> ProgramResult {
+        Self::check_token_accounts(token_program, &[burn_account, mint])?;
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let mut mint_state = spl_token::state::Mint::unpack(&mint.data.borrow())?;
+        let mut source_account = spl_token::state::Account::unpack(&burn_account.data.borrow())?;
+        if source_account.mint != *mint.key {
+            return Err(TokenError::MintMismatch.into());
+        }
+        if source_account.owner != *owner.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if source_account.is_frozen() {
+            return Err(TokenError::AccountFrozen.into());
+        }
+        source_account.amount = source_account
+            .amount
+            .checked_sub(amount)
+            .ok_or(TokenError::InsufficientFunds)?;
+        mint_state.supply = mint_state
+            .supply
+            .checked_sub(amount)
+            .ok_or(TokenError::Overflow)?;
+        spl_token::state::Account::pack(source_account, &mut burn_account.data.borrow_mut())?;
+        spl_token::state::Mint::pack(mint_state, &mut mint.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Cancelled orders leave the book and their funds are unlocked in the open orders at once,
+    /// the Out events the dex would queue are not written as no crank consumes them here.
+    #[allow(clippy::too_many_arguments)]
+    pub fn invoke_dex_cancel_orders_by_client_order_ids(
+        program_id: &Pubkey,
+        dex_program: &AccountInfo,
+        market: &AccountInfo,
+        bids: &AccountInfo,
+        asks: &AccountInfo,
+        open_orders: &AccountInfo,
+        owner: &AccountInfo,
+        client_order_ids: [u64; 8],
+        nonce: u8,
+    ) -> ProgramResult {
+        Self::check_authority(program_id, owner, nonce)?;
+        let market_state = Market::load(market, dex_program.key, true)?;
+        let mut open_orders =
+            market_state.load_orders_mut(open_orders, Some(owner), dex_program.key, None, None)?;
+        let mut bids = market_state.load_bids_mut(bids)?;
+        let mut asks = market_state.load_asks_mut(asks)?;
+        for client_order_id in client_order_ids.iter().filter(|id| **id != 0) {
+            for slot in 0..128 {
+                let slot_mask = 1u128 << slot;
+                if open_orders.free_slot_bits & slot_mask != 0
+                    || open_orders.client_order_ids[slot] != *client_order_id
+                {
+                    continue;
+                }
+                let is_bid = open_orders.is_bid_bits & slot_mask != 0;
+                let order_id = open_orders.orders[slot];
+                let removed = if is_bid {
+                    bids.remove_by_key(order_id)
+                } else {
+                    asks.remove_by_key(order_id)
+                };
+                // filled orders stay in the open orders until their fill event is consumed
+                let leaf = match removed {
+                    Some(leaf) => leaf,
+                    None => continue,
+                };
+                if is_bid {
+                    let native_pc_unlocked = leaf
+                        .quantity()
+                        .checked_mul(leaf.price().get())
+                        .and_then(|qty| qty.checked_mul(market_state.pc_lot_size))
+                        .ok_or(AmmError::CheckedMulOverflow)?;
+                    open_orders.native_pc_free = open_orders
+                        .native_pc_free
+                        .checked_add(native_pc_unlocked)
+                        .ok_or(AmmError::CheckedAddOverflow)?;
+                } else {
+                    let native_coin_unlocked = leaf
+                        .quantity()
+                        .checked_mul(market_state.coin_lot_size)
+                        .ok_or(AmmError::CheckedMulOverflow)?;
+                    open_orders.native_coin_free = open_orders
+                        .native_coin_free
+                        .checked_add(native_coin_unlocked)
+                        .ok_or(AmmError::CheckedAddOverflow)?;
+                }
+                open_orders.orders[slot] = 0;
+                open_orders.client_order_ids[slot] = 0;
+                open_orders.free_slot_bits |= slot_mask;
+                open_orders.is_bid_bits &= !slot_mask;
+            }
+        }
+        Ok(())
+    }
+
+    /// Free balances of the amm open orders move from the market vaults to the amm vaults.
+    /// The referrer rebate goes to `referrer_pc_wallet` when given and to `pc_wallet` otherwise.
+    #[allow(clippy::too_many_arguments)]
+    pub fn invoke_dex_settle_funds(
+        program_id: &Pubkey,
+        dex_program: &AccountInfo,
+        market: &AccountInfo,
+        open_orders: &AccountInfo,
+        owner: &AccountInfo,
+        coin_vault: &AccountInfo,
+        pc_vault: &AccountInfo,
+        coin_wallet: &AccountInfo,
+        pc_wallet: &AccountInfo,
+        vault_signer: &AccountInfo,
+        spl_token_program: &AccountInfo,
+        referrer_pc_wallet: Option<&AccountInfo>,
+        nonce: u8,
+    ) -> ProgramResult {
+        Self::check_authority(program_id, owner, nonce)?;
+        Self::check_token_accounts(spl_token_program, &[coin_vault, pc_vault, coin_wallet, pc_wallet])?;
+        let (native_coin_amount, mut native_pc_amount, referrer_rebate) = {
+            let mut market_state = Market::load(market, dex_program.key, true)?;
+            if identity(market_state.coin_vault) != coin_vault.key.to_aligned_bytes()
+                || identity(market_state.pc_vault) != pc_vault.key.to_aligned_bytes()
+            {
+                return Err(AmmError::InvalidMarket.into());
+            }
+            if gen_vault_signer_key(market_state.vault_signer_nonce, market.key, dex_program.key)?
+                != *vault_signer.key
+            {
+                return Err(AmmError::InvalidMarket.into());
+            }
+            let mut open_orders = market_state.load_orders_mut(
+                open_orders,
+                Some(owner),
+                dex_program.key,
+                None,
+                None,
+            )?;
+            let native_coin_amount = open_orders.native_coin_free;
+            let native_pc_amount = open_orders.native_pc_free;
+            let referrer_rebate = open_orders.referrer_rebates_accrued;
+
+            open_orders.native_coin_total = open_orders
+                .native_coin_total
+                .checked_sub(native_coin_amount)
+                .ok_or(AmmError::CheckedSubOverflow)?;
+            open_orders.native_pc_total = open_orders
+                .native_pc_total
+                .checked_sub(native_pc_amount)
+                .ok_or(AmmError::CheckedSubOverflow)?;
+            open_orders.native_coin_free = 0;
+            open_orders.native_pc_free = 0;
+            open_orders.referrer_rebates_accrued = 0;
+
+            market_state.coin_deposits_total = market_state
+                .coin_deposits_total
+                .checked_sub(native_coin_amount)
+                .ok_or(AmmError::CheckedSubOverflow)?;
+            market_state.pc_deposits_total = market_state
+                .pc_deposits_total
+                .checked_sub(native_pc_amount)
+                .ok_or(AmmError::CheckedSubOverflow)?;
+            market_state.referrer_rebates_accrued = market_state
+                .referrer_rebates_accrued
+                .checked_sub(referrer_rebate)
+                .ok_or(AmmError::CheckedSubOverflow)?;
+            (native_coin_amount, native_pc_amount, referrer_rebate)
+        };
+
+        match referrer_pc_wallet {
+            Some(referrer_pc_wallet) if referrer_rebate > 0 => {
+                Self::check_token_accounts(spl_token_program, &[referrer_pc_wallet])?;
+                Self::transfer(pc_vault, referrer_pc_wallet, vault_signer, referrer_rebate)?;
+            }
+            _ => {
+                native_pc_amount = native_pc_amount
+                    .checked_add(referrer_rebate)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+            }
+        }
+        if native_coin_amount > 0 {
+            Self::transfer(coin_vault, coin_wallet, vault_signer, native_coin_amount)?;
+        }
+        if native_pc_amount > 0 {
+            Self::transfer(pc_vault, pc_wallet, vault_signer, native_pc_amount)?;
+        }
+        Ok(())
+    }
+}
+
 /// Program state handler.
 pub struct Processor {}
 
@@ -279,6 +631,26 @@ impl Processor {
         Ok((bids_orders, asks_orders))
     }
 
+    /// Client order ids of `orders`, 8 for each dex cancel
+    fn get_amm_order_ids(orders: &[LeafNode]) -> Vec<[u64; 8]> {
+        let mut amm_order_ids_vec = Vec::new();
+        let mut order_ids = [0u64; 8];
+        let mut count = 0;
+        for order in orders.iter() {
+            order_ids[count] = order.client_order_id();
+            count += 1;
+            if count == 8 {
+                amm_order_ids_vec.push(order_ids);
+                order_ids = [0u64; 8];
+                count = 0;
+            }
+        }
+        if count != 0 {
+            amm_order_ids_vec.push(order_ids);
+        }
+        amm_order_ids_vec
+    }
+
     pub fn get_amm_best_price(
         market_state: &MarketState,
         open_orders: &OpenOrders,
@@ -449,17 +821,16 @@ impl Processor {
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         swap: SwapInstructionBaseIn,
-    ) -> ProgramResult {
+    ) -> Result<SwapResult, ProgramError> {
         const ACCOUNT_LEN: usize = 17;
         let input_account_len = accounts.len();
-
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
         let account_info_iter = &mut accounts.iter();
         let token_program_info = next_account_info(account_info_iter)?;
 
         let amm_info = next_account_info(account_info_iter)?;
-        println!("amm_info={:?}",amm_info.key);
-        println!("amm_info_owner={:?}",amm_info.owner);
-        println!("amm_info_data={:?}",amm_info.data);
         let amm_authority_info = next_account_info(account_info_iter)?;
         let amm_open_orders_info = next_account_info(account_info_iter)?;
         if input_account_len == ACCOUNT_LEN + 1 {
@@ -489,6 +860,20 @@ impl Processor {
         let user_destination_info = next_account_info(account_info_iter)?;
         let user_source_owner = next_account_info(account_info_iter)?;
 
+        if !user_source_owner.is_signer {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if *amm_coin_vault_info.key != amm.coin_vault {
+            return Err(AmmError::InvalidCoinVault.into());
+        }
+        if *amm_pc_vault_info.key != amm.pc_vault {
+            return Err(AmmError::InvalidPCVault.into());
+        }
 
         let spl_token_program_id = token_program_info.key;
 
@@ -570,11 +955,13 @@ impl Processor {
 
         let swap_fee = U128::from(swap.amount_in)
             .checked_mul(amm.fees.swap_fee_numerator.into())
-            .unwrap()
+            .ok_or(AmmError::CheckedMulOverflow)?
             .checked_ceil_div(amm.fees.swap_fee_denominator.into())
-            .unwrap()
+            .ok_or(AmmError::CheckedDivOverflow)?
             .0;
-        let swap_in_after_deduct_fee = U128::from(swap.amount_in).checked_sub(swap_fee).unwrap();
+        let swap_in_after_deduct_fee = U128::from(swap.amount_in)
+            .checked_sub(swap_fee)
+            .ok_or(AmmError::CheckedSubOverflow)?;
         let swap_amount_out = Calculator::swap_token_amount_base_in(
             swap_in_after_deduct_fee,
             total_pc_without_take_pnl.into(),
@@ -582,6 +969,9 @@ impl Processor {
             swap_direction,
         )
             .as_u64();
+        if swap_amount_out < swap.minimum_amount_out {
+            return Err(AmmError::ExceededSlippage.into());
+        }
         match swap_direction {
             SwapDirection::Coin2PC => {
                 if swap_amount_out >= total_pc_without_take_pnl {
@@ -590,47 +980,73 @@ impl Processor {
 
                 if enable_orderbook {
                     // coin -> pc, need cancel buy order
-                    if !bids.is_empty() {
-                        let mut amm_order_ids_vec = Vec::new();
-                        let mut order_ids = [0u64; 8];
-                        let mut count = 0;
-                        // fetch cancel order ids{
-                        for order in bids.into_iter() {
-                            order_ids[count] = order.client_order_id();
-                            count += 1;
-                            if count == 8 {
-                                amm_order_ids_vec.push(order_ids);
-                                order_ids = [0u64; 8];
-                                count = 0;
-                            }
-                        }
-                        if count != 0 {
-                            amm_order_ids_vec.push(order_ids);
-                        }
-                        for ids in amm_order_ids_vec.iter() {}
+                    for ids in Self::get_amm_order_ids(&bids).iter() {
+                        Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                            program_id,
+                            market_porgram_info,
+                            market_info,
+                            market_bids_info,
+                            market_asks_info,
+                            amm_open_orders_info,
+                            amm_authority_info,
+                            *ids,
+                            amm.nonce as u8,
+                        )?;
                     }
 
-                    if swap_amount_out > amm_pc_vault.amount {}
+                    if swap_amount_out > amm_pc_vault.amount {
+                        Invokers::invoke_dex_settle_funds(
+                            program_id,
+                            market_porgram_info,
+                            market_info,
+                            amm_open_orders_info,
+                            amm_authority_info,
+                            market_coin_vault_info,
+                            market_pc_vault_info,
+                            amm_coin_vault_info,
+                            amm_pc_vault_info,
+                            market_vault_signer,
+                            token_program_info,
+                            None,
+                            amm.nonce as u8,
+                        )?;
+                    }
                 }
 
+                Invokers::token_transfer(
+                    token_program_info,
+                    user_source_info,
+                    amm_coin_vault_info,
+                    user_source_owner,
+                    swap.amount_in,
+                )?;
+                Invokers::token_transfer_with_authority(
+                    program_id,
+                    token_program_info,
+                    amm_pc_vault_info,
+                    user_destination_info,
+                    amm_authority_info,
+                    amm.nonce as u8,
+                    swap_amount_out,
+                )?;
 
                 // update state_data data
                 amm.state_data.swap_coin_in_amount = amm
                     .state_data
                     .swap_coin_in_amount
                     .checked_add(swap.amount_in.into())
-                    .unwrap();
+                    .ok_or(AmmError::CheckedAddOverflow)?;
                 amm.state_data.swap_pc_out_amount = amm
                     .state_data
                     .swap_pc_out_amount
                     .checked_add(swap_amount_out.into())
-                    .unwrap();
+                    .ok_or(AmmError::CheckedAddOverflow)?;
                 // charge coin as swap fee
                 amm.state_data.swap_acc_coin_fee = amm
                     .state_data
                     .swap_acc_coin_fee
                     .checked_add(swap_fee.as_u64())
-                    .unwrap();
+                    .ok_or(AmmError::CheckedAddOverflow)?;
             }
             SwapDirection::PC2Coin => {
                 if swap_amount_out >= total_coin_without_take_pnl {
@@ -639,111 +1055,211 @@ impl Processor {
 
                 if enable_orderbook {
                     // pc -> coin, need cancel sell order
-                    if !asks.is_empty() {
-                        let mut amm_order_ids_vec = Vec::new();
-                        let mut order_ids = [0u64; 8];
-                        let mut count = 0;
-                        // fetch cancel order ids{
-                        for order in asks.into_iter() {
-                            order_ids[count] = order.client_order_id();
-                            count += 1;
-                            if count == 8 {
-                                amm_order_ids_vec.push(order_ids);
-                                order_ids = [0u64; 8];
-                                count = 0;
-                            }
-                        }
-                        if count != 0 {
-                            amm_order_ids_vec.push(order_ids);
-                        }
-                        for ids in amm_order_ids_vec.iter() {}
+                    for ids in Self::get_amm_order_ids(&asks).iter() {
+                        Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                            program_id,
+                            market_porgram_info,
+                            market_info,
+                            market_bids_info,
+                            market_asks_info,
+                            amm_open_orders_info,
+                            amm_authority_info,
+                            *ids,
+                            amm.nonce as u8,
+                        )?;
                     }
 
-                    if swap_amount_out > amm_coin_vault.amount {}
+                    if swap_amount_out > amm_coin_vault.amount {
+                        Invokers::invoke_dex_settle_funds(
+                            program_id,
+                            market_porgram_info,
+                            market_info,
+                            amm_open_orders_info,
+                            amm_authority_info,
+                            market_coin_vault_info,
+                            market_pc_vault_info,
+                            amm_coin_vault_info,
+                            amm_pc_vault_info,
+                            market_vault_signer,
+                            token_program_info,
+                            None,
+                            amm.nonce as u8,
+                        )?;
+                    }
                 }
 
+                Invokers::token_transfer(
+                    token_program_info,
+                    user_source_info,
+                    amm_pc_vault_info,
+                    user_source_owner,
+                    swap.amount_in,
+                )?;
+                Invokers::token_transfer_with_authority(
+                    program_id,
+                    token_program_info,
+                    amm_coin_vault_info,
+                    user_destination_info,
+                    amm_authority_info,
+                    amm.nonce as u8,
+                    swap_amount_out,
+                )?;
+
                 // update state_data data
                 amm.state_data.swap_pc_in_amount = amm
                     .state_data
                     .swap_pc_in_amount
                     .checked_add(swap.amount_in.into())
-                    .unwrap();
+                    .ok_or(AmmError::CheckedAddOverflow)?;
                 amm.state_data.swap_coin_out_amount = amm
                     .state_data
                     .swap_coin_out_amount
                     .checked_add(swap_amount_out.into())
-                    .unwrap();
+                    .ok_or(AmmError::CheckedAddOverflow)?;
                 // charge pc as swap fee
                 amm.state_data.swap_acc_pc_fee = amm
                     .state_data
                     .swap_acc_pc_fee
                     .checked_add(swap_fee.as_u64())
-                    .unwrap();
+                    .ok_or(AmmError::CheckedAddOverflow)?;
             }
         };
 
-        Ok(())
+        let user_source = Self::unpack_token_account(&user_source_info, spl_token_program_id)?;
+        let user_destination =
+            Self::unpack_token_account(&user_destination_info, spl_token_program_id)?;
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        Ok(SwapResult {
+            swap_direction,
+            amount_in: swap.amount_in,
+            amount_out: swap_amount_out,
+            swap_fee: swap_fee.as_u64(),
+            user_source_amount: user_source.amount,
+            user_destination_amount: user_destination.amount,
+            amm_coin_vault_amount: amm_coin_vault.amount,
+            amm_pc_vault_amount: amm_pc_vault.amount,
+        })
     }
 
-    /// Processes an [Instruction](enum.Instruction.html).
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
-        let instruction = AmmInstruction::unpack(input)?;
-        match instruction {
-            AmmInstruction::SwapBaseIn(swap) => {
-                Self::process_swap_base_in(program_id, accounts, swap)
-            }
-            _ => { Ok(()) }
+    fn get_swap_direction(
+        user_source: &spl_token::state::Account,
+        user_destination: &spl_token::state::Account,
+        amm_coin_vault: &spl_token::state::Account,
+        amm_pc_vault: &spl_token::state::Account,
+    ) -> Result<SwapDirection, AmmError> {
+        if user_source.mint == amm_coin_vault.mint && user_destination.mint == amm_pc_vault.mint {
+            Ok(SwapDirection::Coin2PC)
+        } else if user_source.mint == amm_pc_vault.mint
+            && user_destination.mint == amm_coin_vault.mint
+        {
+            Ok(SwapDirection::PC2Coin)
+        } else {
+            Err(AmmError::InvalidUserToken)
         }
     }
-}
 
-pub mod account_parser {
-    use crate::raydium::state::AmmInfo;
-    use super::*;
+    pub fn process_swap_base_out(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        swap: SwapInstructionBaseOut,
+    ) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 17;
+        let input_account_len = accounts.len();
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
 
-    pub struct IdleArgs<'a> {
-        pub program_id: &'a Pubkey,
-        pub total_coin_without_take_pnl: &'a mut u64,
-        pub total_pc_without_take_pnl: &'a mut u64,
-        pub amm: &'a mut AmmInfo,
-        pub bids: &'a Vec<LeafNode>,
-        pub asks: &'a Vec<LeafNode>,
-        pub target: &'a mut TargetOrders,
-    }
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        if input_account_len == ACCOUNT_LEN + 1 {
+            let _amm_target_orders_info = next_account_info(account_info_iter)?;
+        }
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
 
-    impl<'a> IdleArgs<'a> {
-        pub fn with_parsed_args(
-            program_id: &'a Pubkey,
-            spl_token_program_id: &'a Pubkey,
-            amm: &mut AmmInfo,
-            accounts: &'a [AccountInfo],
-            f: impl FnOnce(IdleArgs) -> ProgramResult,
-        ) -> ProgramResult {
-            if accounts.len() != 11 {
-                return Err(AmmError::WrongAccountsNumber.into());
-            }
-            let &[ref amm_info, ref _market_program_info, ref market_info, ref market_bids_info, ref market_asks_info, ref market_event_queue_info, ref market_authority_info, ref amm_open_orders_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
-                array_ref![accounts, 0, 11];
+        let market_program_info = next_account_info(account_info_iter)?;
 
-            let amm_coin_vault =
-                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
-            let amm_pc_vault =
-                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
-            let mut target =
-                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        let enable_orderbook = AmmStatus::from_u64(amm.status).orderbook_permission();
+        let market_info = next_account_info(account_info_iter)?;
+        let market_bids_info = next_account_info(account_info_iter)?;
+        let market_asks_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        let market_coin_vault_info = next_account_info(account_info_iter)?;
+        let market_pc_vault_info = next_account_info(account_info_iter)?;
+        let market_vault_signer = next_account_info(account_info_iter)?;
+
+        let user_source_info = next_account_info(account_info_iter)?;
+        let user_destination_info = next_account_info(account_info_iter)?;
+        let user_source_owner = next_account_info(account_info_iter)?;
+
+        if !user_source_owner.is_signer {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if *amm_coin_vault_info.key != amm.coin_vault {
+            return Err(AmmError::InvalidCoinVault.into());
+        }
+        if *amm_pc_vault_info.key != amm.pc_vault {
+            return Err(AmmError::InvalidPCVault.into());
+        }
+
+        let spl_token_program_id = token_program_info.key;
+
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+
+        let user_source = Self::unpack_token_account(&user_source_info, spl_token_program_id)?;
+        let user_destination =
+            Self::unpack_token_account(&user_destination_info, spl_token_program_id)?;
+
+        if !AmmStatus::from_u64(amm.status).swap_permission() {
+            msg!(&format!("swap_base_out: status {}", amm.status));
+            let clock = Clock::get()?;
+            if amm.status == AmmStatus::OrderBookOnly.into_u64()
+                && (clock.unix_timestamp as u64) >= amm.state_data.orderbook_to_init_time
+            {
+                amm.status = AmmStatus::Initialized.into_u64();
+                msg!("swap_base_out: OrderBook to Initialized");
+            } else {
+                return Err(AmmError::InvalidStatus.into());
+            }
+        } else if amm.status == AmmStatus::WaitingTrade.into_u64() {
+            let clock = Clock::get()?;
+            if (clock.unix_timestamp as u64) < amm.state_data.pool_open_time {
+                return Err(AmmError::InvalidStatus.into());
+            } else {
+                amm.status = AmmStatus::SwapOnly.into_u64();
+                msg!("swap_base_out: WaitingTrade to SwapOnly");
+            }
+        }
 
+        let total_pc_without_take_pnl;
+        let total_coin_without_take_pnl;
+        let mut bids: Vec<LeafNode> = Vec::new();
+        let mut asks: Vec<LeafNode> = Vec::new();
+        if enable_orderbook {
             let (market_state, open_orders) = Processor::load_serum_market_order(
                 market_info,
                 amm_open_orders_info,
-                market_authority_info,
+                amm_authority_info,
                 &amm,
                 false,
             )?;
             let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
             let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
-            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
-
-            let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            (bids, asks) = Self::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+            (total_pc_without_take_pnl, total_coin_without_take_pnl) =
                 Calculator::calc_total_without_take_pnl(
                     amm_pc_vault.amount,
                     amm_coin_vault.amount,
@@ -753,12 +1269,1225 @@ pub mod account_parser {
                     &market_event_queue_info,
                     &amm_open_orders_info,
                 )?;
+        } else {
+            let open_orders = Self::load_orders(amm_open_orders_info)?;
+            (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+                Calculator::calc_total_without_take_pnl_no_orderbook(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                )?;
+        }
 
-            let args = IdleArgs {
-                program_id,
-                total_coin_without_take_pnl: &mut total_coin_without_take_pnl,
-                total_pc_without_take_pnl: &mut total_pc_without_take_pnl,
-                amm,
+        let swap_direction = Self::get_swap_direction(
+            &user_source,
+            &user_destination,
+            &amm_coin_vault,
+            &amm_pc_vault,
+        )?;
+        let swap_quote = quote::swap_base_out_without_take_pnl(
+            &amm,
+            total_pc_without_take_pnl,
+            total_coin_without_take_pnl,
+            swap.amount_out,
+            swap_direction,
+        )?;
+        if swap.max_amount_in < swap_quote.amount_in {
+            return Err(AmmError::ExceededSlippage.into());
+        }
+
+        if enable_orderbook {
+            // coin -> pc, need cancel buy order; pc -> coin, need cancel sell order
+            let (orders, vault_amount) = match swap_direction {
+                SwapDirection::Coin2PC => (&bids, amm_pc_vault.amount),
+                SwapDirection::PC2Coin => (&asks, amm_coin_vault.amount),
+            };
+            for ids in Self::get_amm_order_ids(orders).iter() {
+                Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                    program_id,
+                    market_program_info,
+                    market_info,
+                    market_bids_info,
+                    market_asks_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    *ids,
+                    amm.nonce as u8,
+                )?;
+            }
+            if swap.amount_out > vault_amount {
+                Invokers::invoke_dex_settle_funds(
+                    program_id,
+                    market_program_info,
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    market_coin_vault_info,
+                    market_pc_vault_info,
+                    amm_coin_vault_info,
+                    amm_pc_vault_info,
+                    market_vault_signer,
+                    token_program_info,
+                    None,
+                    amm.nonce as u8,
+                )?;
+            }
+        }
+
+        match swap_direction {
+            SwapDirection::Coin2PC => {
+                Invokers::token_transfer(
+                    token_program_info,
+                    user_source_info,
+                    amm_coin_vault_info,
+                    user_source_owner,
+                    swap_quote.amount_in,
+                )?;
+                Invokers::token_transfer_with_authority(
+                    program_id,
+                    token_program_info,
+                    amm_pc_vault_info,
+                    user_destination_info,
+                    amm_authority_info,
+                    amm.nonce as u8,
+                    swap.amount_out,
+                )?;
+                // update state_data data
+                amm.state_data.swap_coin_in_amount = amm
+                    .state_data
+                    .swap_coin_in_amount
+                    .checked_add(swap_quote.amount_in.into())
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+                amm.state_data.swap_pc_out_amount = amm
+                    .state_data
+                    .swap_pc_out_amount
+                    .checked_add(swap.amount_out.into())
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+                // charge coin as swap fee
+                amm.state_data.swap_acc_coin_fee = amm
+                    .state_data
+                    .swap_acc_coin_fee
+                    .checked_add(swap_quote.fee)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+            }
+            SwapDirection::PC2Coin => {
+                Invokers::token_transfer(
+                    token_program_info,
+                    user_source_info,
+                    amm_pc_vault_info,
+                    user_source_owner,
+                    swap_quote.amount_in,
+                )?;
+                Invokers::token_transfer_with_authority(
+                    program_id,
+                    token_program_info,
+                    amm_coin_vault_info,
+                    user_destination_info,
+                    amm_authority_info,
+                    amm.nonce as u8,
+                    swap.amount_out,
+                )?;
+                // update state_data data
+                amm.state_data.swap_pc_in_amount = amm
+                    .state_data
+                    .swap_pc_in_amount
+                    .checked_add(swap_quote.amount_in.into())
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+                amm.state_data.swap_coin_out_amount = amm
+                    .state_data
+                    .swap_coin_out_amount
+                    .checked_add(swap.amount_out.into())
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+                // charge pc as swap fee
+                amm.state_data.swap_acc_pc_fee = amm
+                    .state_data
+                    .swap_acc_pc_fee
+                    .checked_add(swap_quote.fee)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+            }
+        };
+
+        Ok(())
+    }
+
+    pub fn process_deposit(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        deposit: DepositInstruction,
+    ) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 14;
+        let input_account_len = accounts.len();
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+        let amm_lp_mint_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+
+        let user_source_coin_info = next_account_info(account_info_iter)?;
+        let user_source_pc_info = next_account_info(account_info_iter)?;
+        let user_dest_lp_info = next_account_info(account_info_iter)?;
+        let source_owner_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        if deposit.max_coin_amount == 0 || deposit.max_pc_amount == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
+        if !source_owner_info.is_signer {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if !AmmStatus::from_u64(amm.status).deposit_permission() {
+            return Err(AmmError::InvalidStatus.into());
+        }
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if *token_program_info.key != spl_token::id() {
+            return Err(AmmError::InvalidSplTokenProgram.into());
+        }
+        if *amm_coin_vault_info.key != amm.coin_vault {
+            return Err(AmmError::InvalidCoinVault.into());
+        }
+        if *amm_pc_vault_info.key != amm.pc_vault {
+            return Err(AmmError::InvalidPCVault.into());
+        }
+        if *amm_lp_mint_info.key != amm.lp_mint {
+            return Err(AmmError::InvalidPoolMint.into());
+        }
+        if *amm_open_orders_info.key != amm.open_orders {
+            return Err(AmmError::InvalidOpenOrders.into());
+        }
+        if *market_info.key != amm.market {
+            return Err(AmmError::InvalidMarket.into());
+        }
+        if *amm_target_orders_info.key != amm.target_orders {
+            return Err(AmmError::InvalidTargetOrders.into());
+        }
+
+        let spl_token_program_id = token_program_info.key;
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        let user_source_coin =
+            Self::unpack_token_account(&user_source_coin_info, spl_token_program_id)?;
+        let user_source_pc =
+            Self::unpack_token_account(&user_source_pc_info, spl_token_program_id)?;
+        let mut target =
+            TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+
+        let enable_orderbook = AmmStatus::from_u64(amm.status).orderbook_permission();
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            if enable_orderbook {
+                let (market_state, open_orders) = Processor::load_serum_market_order(
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    &amm,
+                    false,
+                )?;
+                Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                    &market_state,
+                    &market_event_queue_info,
+                    &amm_open_orders_info,
+                )?
+            } else {
+                let open_orders = Self::load_orders(amm_open_orders_info)?;
+                Calculator::calc_total_without_take_pnl_no_orderbook(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                )?
+            };
+
+        let x1 = Calculator::normalize_decimal_v2(
+            total_pc_without_take_pnl,
+            amm.pc_decimals,
+            amm.sys_decimal_value,
+        );
+        let y1 = Calculator::normalize_decimal_v2(
+            total_coin_without_take_pnl,
+            amm.coin_decimals,
+            amm.sys_decimal_value,
+        );
+        // calc and update pnl
+        let (delta_x, delta_y) = Self::calc_take_pnl(
+            &target,
+            &mut amm,
+            &mut total_pc_without_take_pnl,
+            &mut total_coin_without_take_pnl,
+            x1.as_u128().into(),
+            y1.as_u128().into(),
+        )?;
+
+        let invariant = InvariantToken {
+            token_coin: total_coin_without_take_pnl,
+            token_pc: total_pc_without_take_pnl,
+        };
+        if amm.lp_amount == 0 {
+            return Err(AmmError::NotAllowZeroLP.into());
+        }
+        let deduct_pc_amount;
+        let deduct_coin_amount;
+        let mint_lp_amount;
+        if deposit.base_side == 0 {
+            // base coin
+            deduct_pc_amount = invariant
+                .exchange_coin_to_pc(deposit.max_coin_amount, RoundDirection::Ceiling)
+                .ok_or(AmmError::CalculationExRateFailure)?;
+            deduct_coin_amount = deposit.max_coin_amount;
+            if deduct_pc_amount > deposit.max_pc_amount {
+                return Err(AmmError::ExceededSlippage.into());
+            }
+            // base coin, check other_amount_min if need
+            mint_lp_amount = InvariantPool {
+                token_input: deduct_coin_amount,
+                token_total: total_coin_without_take_pnl,
+            }
+            .exchange_token_to_pool(amm.lp_amount, RoundDirection::Floor)
+            .ok_or(AmmError::CalculationExRateFailure)?;
+        } else {
+            // base pc
+            deduct_coin_amount = invariant
+                .exchange_pc_to_coin(deposit.max_pc_amount, RoundDirection::Ceiling)
+                .ok_or(AmmError::CalculationExRateFailure)?;
+            deduct_pc_amount = deposit.max_pc_amount;
+            if deduct_coin_amount > deposit.max_coin_amount {
+                return Err(AmmError::ExceededSlippage.into());
+            }
+            mint_lp_amount = InvariantPool {
+                token_input: deduct_pc_amount,
+                token_total: total_pc_without_take_pnl,
+            }
+            .exchange_token_to_pool(amm.lp_amount, RoundDirection::Floor)
+            .ok_or(AmmError::CalculationExRateFailure)?;
+        }
+        msg!(&format!(
+            "deposit: deduct_coin_amount {}, deduct_pc_amount {}, mint_lp_amount {}",
+            deduct_coin_amount, deduct_pc_amount, mint_lp_amount
+        ));
+        if deduct_coin_amount > user_source_coin.amount || deduct_pc_amount > user_source_pc.amount
+        {
+            return Err(AmmError::InsufficientFunds.into());
+        }
+        if mint_lp_amount == 0 || deduct_coin_amount == 0 || deduct_pc_amount == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
+
+        Invokers::token_transfer(
+            token_program_info,
+            user_source_coin_info,
+            amm_coin_vault_info,
+            source_owner_info,
+            deduct_coin_amount,
+        )?;
+        Invokers::token_transfer(
+            token_program_info,
+            user_source_pc_info,
+            amm_pc_vault_info,
+            source_owner_info,
+            deduct_pc_amount,
+        )?;
+        Invokers::token_mint_to(
+            program_id,
+            token_program_info,
+            amm_lp_mint_info,
+            user_dest_lp_info,
+            amm_authority_info,
+            amm.nonce as u8,
+            mint_lp_amount,
+        )?;
+        amm.lp_amount = amm
+            .lp_amount
+            .checked_add(mint_lp_amount)
+            .ok_or(AmmError::CheckedAddOverflow)?;
+
+        target.calc_pnl_x = x1
+            .checked_add(Calculator::normalize_decimal_v2(
+                deduct_pc_amount,
+                amm.pc_decimals,
+                amm.sys_decimal_value,
+            ))
+            .ok_or(AmmError::CheckedAddOverflow)?
+            .checked_sub(U128::from(delta_x))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .as_u128();
+        target.calc_pnl_y = y1
+            .checked_add(Calculator::normalize_decimal_v2(
+                deduct_coin_amount,
+                amm.coin_decimals,
+                amm.sys_decimal_value,
+            ))
+            .ok_or(AmmError::CheckedAddOverflow)?
+            .checked_sub(U128::from(delta_y))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .as_u128();
+
+        Ok(())
+    }
+
+    pub fn process_withdraw(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        withdraw: WithdrawInstruction,
+    ) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 20;
+        let input_account_len = accounts.len();
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+        let amm_lp_mint_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+
+        let market_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let market_coin_vault_info = next_account_info(account_info_iter)?;
+        let market_pc_vault_info = next_account_info(account_info_iter)?;
+        let market_vault_signer = next_account_info(account_info_iter)?;
+
+        let user_source_lp_info = next_account_info(account_info_iter)?;
+        let user_dest_coin_info = next_account_info(account_info_iter)?;
+        let user_dest_pc_info = next_account_info(account_info_iter)?;
+        let source_lp_owner_info = next_account_info(account_info_iter)?;
+
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        let market_bids_info = next_account_info(account_info_iter)?;
+        let market_asks_info = next_account_info(account_info_iter)?;
+        let referrer_pc_info = if input_account_len == ACCOUNT_LEN + 1 {
+            Some(next_account_info(account_info_iter)?)
+        } else {
+            None
+        };
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        if !source_lp_owner_info.is_signer {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if !AmmStatus::from_u64(amm.status).withdraw_permission() {
+            return Err(AmmError::InvalidStatus.into());
+        }
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if *token_program_info.key != spl_token::id() {
+            return Err(AmmError::InvalidSplTokenProgram.into());
+        }
+        if *amm_coin_vault_info.key != amm.coin_vault {
+            return Err(AmmError::InvalidCoinVault.into());
+        }
+        if *amm_pc_vault_info.key != amm.pc_vault {
+            return Err(AmmError::InvalidPCVault.into());
+        }
+        if *amm_lp_mint_info.key != amm.lp_mint {
+            return Err(AmmError::InvalidPoolMint.into());
+        }
+        if *amm_open_orders_info.key != amm.open_orders {
+            return Err(AmmError::InvalidOpenOrders.into());
+        }
+        if *market_info.key != amm.market {
+            return Err(AmmError::InvalidMarket.into());
+        }
+        if *amm_target_orders_info.key != amm.target_orders {
+            return Err(AmmError::InvalidTargetOrders.into());
+        }
+
+        let spl_token_program_id = token_program_info.key;
+        let mut amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let mut amm_pc_vault =
+            Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        let user_source_lp =
+            Self::unpack_token_account(&user_source_lp_info, spl_token_program_id)?;
+        if withdraw.amount == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
+        if user_source_lp.amount < withdraw.amount {
+            return Err(AmmError::InsufficientFunds.into());
+        }
+        if withdraw.amount >= amm.lp_amount {
+            return Err(AmmError::NotAllowZeroLP.into());
+        }
+        let mut target =
+            TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+
+        let enable_orderbook = AmmStatus::from_u64(amm.status).orderbook_permission();
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            if enable_orderbook {
+                let (market_state, open_orders) = Processor::load_serum_market_order(
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    &amm,
+                    false,
+                )?;
+                Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                    &market_state,
+                    &market_event_queue_info,
+                    &amm_open_orders_info,
+                )?
+            } else {
+                let open_orders = Self::load_orders(amm_open_orders_info)?;
+                Calculator::calc_total_without_take_pnl_no_orderbook(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                )?
+            };
+
+        let x1 = Calculator::normalize_decimal_v2(
+            total_pc_without_take_pnl,
+            amm.pc_decimals,
+            amm.sys_decimal_value,
+        );
+        let y1 = Calculator::normalize_decimal_v2(
+            total_coin_without_take_pnl,
+            amm.coin_decimals,
+            amm.sys_decimal_value,
+        );
+        let mut delta_x = 0;
+        let mut delta_y = 0;
+        if amm.status != AmmStatus::WithdrawOnly.into_u64() {
+            // calc and update pnl
+            (delta_x, delta_y) = Self::calc_take_pnl(
+                &target,
+                &mut amm,
+                &mut total_pc_without_take_pnl,
+                &mut total_coin_without_take_pnl,
+                x1.as_u128().into(),
+                y1.as_u128().into(),
+            )?;
+        }
+
+        let invariant = InvariantPool {
+            token_input: withdraw.amount,
+            token_total: amm.lp_amount,
+        };
+        let coin_amount = invariant
+            .exchange_pool_to_token(total_coin_without_take_pnl, RoundDirection::Floor)
+            .ok_or(AmmError::CalculationExRateFailure)?;
+        let pc_amount = invariant
+            .exchange_pool_to_token(total_pc_without_take_pnl, RoundDirection::Floor)
+            .ok_or(AmmError::CalculationExRateFailure)?;
+        msg!(&format!(
+            "withdraw: lp_amount {}, coin_amount {}, pc_amount {}",
+            withdraw.amount, coin_amount, pc_amount
+        ));
+        if coin_amount == 0 || pc_amount == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
+        if enable_orderbook
+            && (coin_amount > amm_coin_vault.amount || pc_amount > amm_pc_vault.amount)
+        {
+            // cancel all amm orders and settle the funds back to the vaults
+            let (bids, asks) = {
+                let (market_state, open_orders) = Processor::load_serum_market_order(
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    &amm,
+                    false,
+                )?;
+                let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
+                let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
+                Self::get_amm_orders(&open_orders, bids_orders, asks_orders)?
+            };
+            let mut amm_order_ids_vec = Self::get_amm_order_ids(&bids);
+            amm_order_ids_vec.extend(Self::get_amm_order_ids(&asks));
+            for ids in amm_order_ids_vec.iter() {
+                Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                    program_id,
+                    market_program_info,
+                    market_info,
+                    market_bids_info,
+                    market_asks_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    *ids,
+                    amm.nonce as u8,
+                )?;
+            }
+            Invokers::invoke_dex_settle_funds(
+                program_id,
+                market_program_info,
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                market_coin_vault_info,
+                market_pc_vault_info,
+                amm_coin_vault_info,
+                amm_pc_vault_info,
+                market_vault_signer,
+                token_program_info,
+                referrer_pc_info
+                    .filter(|info| *info.key == config_feature::referrer_pc_wallet::id()),
+                amm.nonce as u8,
+            )?;
+            amm_coin_vault =
+                Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+            amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        }
+        if coin_amount > amm_coin_vault.amount || pc_amount > amm_pc_vault.amount {
+            return Err(AmmError::InsufficientFunds.into());
+        }
+
+        Invokers::token_burn(
+            token_program_info,
+            user_source_lp_info,
+            amm_lp_mint_info,
+            source_lp_owner_info,
+            withdraw.amount,
+        )?;
+        amm.lp_amount = amm
+            .lp_amount
+            .checked_sub(withdraw.amount)
+            .ok_or(AmmError::CheckedSubOverflow)?;
+        Invokers::token_transfer_with_authority(
+            program_id,
+            token_program_info,
+            amm_coin_vault_info,
+            user_dest_coin_info,
+            amm_authority_info,
+            amm.nonce as u8,
+            coin_amount,
+        )?;
+        Invokers::token_transfer_with_authority(
+            program_id,
+            token_program_info,
+            amm_pc_vault_info,
+            user_dest_pc_info,
+            amm_authority_info,
+            amm.nonce as u8,
+            pc_amount,
+        )?;
+
+        target.calc_pnl_x = x1
+            .checked_sub(Calculator::normalize_decimal_v2(
+                pc_amount,
+                amm.pc_decimals,
+                amm.sys_decimal_value,
+            ))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .checked_sub(U128::from(delta_x))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .as_u128();
+        target.calc_pnl_y = y1
+            .checked_sub(Calculator::normalize_decimal_v2(
+                coin_amount,
+                amm.coin_decimals,
+                amm.sys_decimal_value,
+            ))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .checked_sub(U128::from(delta_y))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .as_u128();
+
+        Ok(())
+    }
+
+    pub fn process_withdrawpnl(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 17;
+        let input_account_len = accounts.len();
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let user_dest_coin_info = next_account_info(account_info_iter)?;
+        let user_dest_pc_info = next_account_info(account_info_iter)?;
+        let pnl_owner_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+
+        let market_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        let market_coin_vault_info = next_account_info(account_info_iter)?;
+        let market_pc_vault_info = next_account_info(account_info_iter)?;
+        let market_vault_signer = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        let (pda, _) = Pubkey::find_program_address(&[AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+        let amm_config = AmmConfig::load_checked(&amm_config_info, program_id)?;
+        if !pnl_owner_info.is_signer
+            || (*pnl_owner_info.key != amm_config.pnl_owner
+                && *pnl_owner_info.key != config_feature::amm_owner::ID)
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if *token_program_info.key != spl_token::id() {
+            return Err(AmmError::InvalidSplTokenProgram.into());
+        }
+        if *amm_coin_vault_info.key != amm.coin_vault {
+            return Err(AmmError::InvalidCoinVault.into());
+        }
+        if *amm_pc_vault_info.key != amm.pc_vault {
+            return Err(AmmError::InvalidPCVault.into());
+        }
+        if *amm_open_orders_info.key != amm.open_orders {
+            return Err(AmmError::InvalidOpenOrders.into());
+        }
+        if *market_info.key != amm.market {
+            return Err(AmmError::InvalidMarket.into());
+        }
+        if *amm_target_orders_info.key != amm.target_orders {
+            return Err(AmmError::InvalidTargetOrders.into());
+        }
+
+        let enable_orderbook = AmmStatus::from_u64(amm.status).orderbook_permission();
+        if enable_orderbook {
+            Invokers::invoke_dex_settle_funds(
+                program_id,
+                market_program_info,
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                market_coin_vault_info,
+                market_pc_vault_info,
+                amm_coin_vault_info,
+                amm_pc_vault_info,
+                market_vault_signer,
+                token_program_info,
+                None,
+                amm.nonce as u8,
+            )?;
+        }
+
+        let spl_token_program_id = token_program_info.key;
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        let mut target =
+            TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            if enable_orderbook {
+                let (market_state, open_orders) = Processor::load_serum_market_order(
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    &amm,
+                    false,
+                )?;
+                Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                    &market_state,
+                    &market_event_queue_info,
+                    &amm_open_orders_info,
+                )?
+            } else {
+                let open_orders = Self::load_orders(amm_open_orders_info)?;
+                Calculator::calc_total_without_take_pnl_no_orderbook(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                )?
+            };
+
+        let x1 = Calculator::normalize_decimal_v2(
+            total_pc_without_take_pnl,
+            amm.pc_decimals,
+            amm.sys_decimal_value,
+        );
+        let y1 = Calculator::normalize_decimal_v2(
+            total_coin_without_take_pnl,
+            amm.coin_decimals,
+            amm.sys_decimal_value,
+        );
+        // calc and update pnl
+        let (delta_x, delta_y) = Self::calc_take_pnl(
+            &target,
+            &mut amm,
+            &mut total_pc_without_take_pnl,
+            &mut total_coin_without_take_pnl,
+            x1.as_u128().into(),
+            y1.as_u128().into(),
+        )?;
+
+        let pnl_coin_amount = amm.state_data.need_take_pnl_coin;
+        let pnl_pc_amount = amm.state_data.need_take_pnl_pc;
+        if pnl_coin_amount == 0 && pnl_pc_amount == 0 {
+            return Err(AmmError::TakePnlError.into());
+        }
+        if pnl_coin_amount > amm_coin_vault.amount || pnl_pc_amount > amm_pc_vault.amount {
+            return Err(AmmError::TakePnlError.into());
+        }
+        msg!(&format!(
+            "withdrawpnl: coin_amount {}, pc_amount {}",
+            pnl_coin_amount, pnl_pc_amount
+        ));
+
+        Invokers::token_transfer_with_authority(
+            program_id,
+            token_program_info,
+            amm_coin_vault_info,
+            user_dest_coin_info,
+            amm_authority_info,
+            amm.nonce as u8,
+            pnl_coin_amount,
+        )?;
+        Invokers::token_transfer_with_authority(
+            program_id,
+            token_program_info,
+            amm_pc_vault_info,
+            user_dest_pc_info,
+            amm_authority_info,
+            amm.nonce as u8,
+            pnl_pc_amount,
+        )?;
+        amm.state_data.need_take_pnl_coin = 0;
+        amm.state_data.need_take_pnl_pc = 0;
+
+        target.calc_pnl_x = x1
+            .checked_sub(U128::from(delta_x))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .as_u128();
+        target.calc_pnl_y = y1
+            .checked_sub(U128::from(delta_y))
+            .ok_or(AmmError::CheckedSubOverflow)?
+            .as_u128();
+
+        Ok(())
+    }
+
+    /// Pool totals and pnl of an amm, computed on a copy so the accounts are left untouched.
+    #[allow(clippy::too_many_arguments)]
+    fn load_pool_data<'a>(
+        program_id: &Pubkey,
+        amm_info: &AccountInfo<'a>,
+        amm_authority_info: &AccountInfo<'a>,
+        amm_open_orders_info: &AccountInfo<'a>,
+        amm_coin_vault_info: &AccountInfo<'a>,
+        amm_pc_vault_info: &AccountInfo<'a>,
+        amm_lp_mint_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        market_event_queue_info: &AccountInfo<'a>,
+        amm_target_orders_info: Option<&AccountInfo<'a>>,
+    ) -> Result<(AmmInfo, GetPoolData), ProgramError> {
+        let mut amm = *AmmInfo::load_checked(amm_info, program_id)?;
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if *amm_coin_vault_info.key != amm.coin_vault {
+            return Err(AmmError::InvalidCoinVault.into());
+        }
+        if *amm_pc_vault_info.key != amm.pc_vault {
+            return Err(AmmError::InvalidPCVault.into());
+        }
+        if *amm_lp_mint_info.key != amm.lp_mint {
+            return Err(AmmError::InvalidPoolMint.into());
+        }
+        if *amm_open_orders_info.key != amm.open_orders {
+            return Err(AmmError::InvalidOpenOrders.into());
+        }
+        if *market_info.key != amm.market {
+            return Err(AmmError::InvalidMarket.into());
+        }
+
+        let amm_coin_vault = Self::unpack_token_account(amm_coin_vault_info, &spl_token::id())?;
+        let amm_pc_vault = Self::unpack_token_account(amm_pc_vault_info, &spl_token::id())?;
+        let lp_mint = Self::unpack_mint(amm_lp_mint_info, &spl_token::id())?;
+
+        let enable_orderbook = AmmStatus::from_u64(amm.status).orderbook_permission();
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            if enable_orderbook {
+                let (market_state, open_orders) = Processor::load_serum_market_order(
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    &amm,
+                    false,
+                )?;
+                Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                    &market_state,
+                    market_event_queue_info,
+                    amm_open_orders_info,
+                )?
+            } else {
+                let open_orders = Self::load_orders(amm_open_orders_info)?;
+                Calculator::calc_total_without_take_pnl_no_orderbook(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                )?
+            };
+
+        if let Some(amm_target_orders_info) = amm_target_orders_info {
+            if *amm_target_orders_info.key != amm.target_orders {
+                return Err(AmmError::InvalidTargetOrders.into());
+            }
+            let target =
+                TargetOrders::load_checked(amm_target_orders_info, program_id, amm_info.key)?;
+            if amm.status != AmmStatus::WithdrawOnly.into_u64() {
+                let x1 = Calculator::normalize_decimal_v2(
+                    total_pc_without_take_pnl,
+                    amm.pc_decimals,
+                    amm.sys_decimal_value,
+                );
+                let y1 = Calculator::normalize_decimal_v2(
+                    total_coin_without_take_pnl,
+                    amm.coin_decimals,
+                    amm.sys_decimal_value,
+                );
+                Self::calc_take_pnl(
+                    &target,
+                    &mut amm,
+                    &mut total_pc_without_take_pnl,
+                    &mut total_coin_without_take_pnl,
+                    x1.as_u128().into(),
+                    y1.as_u128().into(),
+                )?;
+            }
+        }
+
+        let pool_data = GetPoolData {
+            status: amm.status,
+            coin_decimals: amm.coin_decimals,
+            pc_decimals: amm.pc_decimals,
+            lp_decimals: lp_mint.decimals.into(),
+            pool_pc_amount: total_pc_without_take_pnl,
+            pool_coin_amount: total_coin_without_take_pnl,
+            pnl_pc_amount: amm.state_data.need_take_pnl_pc,
+            pnl_coin_amount: amm.state_data.need_take_pnl_coin,
+            pool_lp_supply: amm.lp_amount,
+            pool_open_time: amm.state_data.pool_open_time,
+            amm_id: amm_info.key.to_string(),
+        };
+        Ok((amm, pool_data))
+    }
+
+    pub fn simulate_pool_info(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> Result<GetPoolData, ProgramError> {
+        const ACCOUNT_LEN: usize = 8;
+        let input_account_len = accounts.len();
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let amm_lp_mint_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = if input_account_len == ACCOUNT_LEN + 1 {
+            Some(next_account_info(account_info_iter)?)
+        } else {
+            None
+        };
+
+        let (_, pool_data) = Self::load_pool_data(
+            program_id,
+            amm_info,
+            amm_authority_info,
+            amm_open_orders_info,
+            amm_coin_vault_info,
+            amm_pc_vault_info,
+            amm_lp_mint_info,
+            market_info,
+            market_event_queue_info,
+            amm_target_orders_info,
+        )?;
+        Ok(pool_data)
+    }
+
+    fn simulate_swap_info<'a>(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo<'a>],
+    ) -> Result<(AmmInfo, GetPoolData, SwapDirection), ProgramError> {
+        const ACCOUNT_LEN: usize = 13;
+        if accounts.len() != ACCOUNT_LEN {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let amm_lp_mint_info = next_account_info(account_info_iter)?;
+        let _market_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        let user_source_info = next_account_info(account_info_iter)?;
+        let user_destination_info = next_account_info(account_info_iter)?;
+        let _user_source_owner = next_account_info(account_info_iter)?;
+
+        let (amm, pool_data) = Self::load_pool_data(
+            program_id,
+            amm_info,
+            amm_authority_info,
+            amm_open_orders_info,
+            amm_coin_vault_info,
+            amm_pc_vault_info,
+            amm_lp_mint_info,
+            market_info,
+            market_event_queue_info,
+            Some(amm_target_orders_info),
+        )?;
+        if !AmmStatus::from_u64(amm.status).swap_permission() {
+            return Err(AmmError::InvalidStatus.into());
+        }
+        let amm_coin_vault = Self::unpack_token_account(amm_coin_vault_info, &spl_token::id())?;
+        let amm_pc_vault = Self::unpack_token_account(amm_pc_vault_info, &spl_token::id())?;
+        let user_source = Self::unpack_token_account(user_source_info, &spl_token::id())?;
+        let user_destination =
+            Self::unpack_token_account(user_destination_info, &spl_token::id())?;
+        let swap_direction = Self::get_swap_direction(
+            &user_source,
+            &user_destination,
+            &amm_coin_vault,
+            &amm_pc_vault,
+        )?;
+        Ok((amm, pool_data, swap_direction))
+    }
+
+    pub fn simulate_swap_base_in(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        swap: SwapInstructionBaseIn,
+    ) -> Result<GetSwapBaseInData, ProgramError> {
+        let (amm, pool_data, swap_direction) = Self::simulate_swap_info(program_id, accounts)?;
+        let swap_quote = quote::swap_base_in_without_take_pnl(
+            &amm,
+            pool_data.pool_pc_amount,
+            pool_data.pool_coin_amount,
+            swap.amount_in,
+            swap_direction,
+        )?;
+        Ok(GetSwapBaseInData {
+            pool_data,
+            amount_in: swap.amount_in,
+            minimum_amount_out: swap_quote.amount_out,
+            price_impact: swap_quote.price_impact,
+        })
+    }
+
+    pub fn simulate_swap_base_out(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        swap: SwapInstructionBaseOut,
+    ) -> Result<GetSwapBaseOutData, ProgramError> {
+        let (amm, pool_data, swap_direction) = Self::simulate_swap_info(program_id, accounts)?;
+        let swap_quote = quote::swap_base_out_without_take_pnl(
+            &amm,
+            pool_data.pool_pc_amount,
+            pool_data.pool_coin_amount,
+            swap.amount_out,
+            swap_direction,
+        )?;
+        Ok(GetSwapBaseOutData {
+            pool_data,
+            max_amount_in: swap_quote.amount_in,
+            amount_out: swap.amount_out,
+            price_impact: swap_quote.price_impact,
+        })
+    }
+
+    /// The monitor step state machine is not run locally, a crank is reported as due
+    /// while the orderbook is enabled and the amm is away from the idle state.
+    pub fn simulate_run_crank(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> Result<RunCrankData, ProgramError> {
+        const ACCOUNT_LEN: usize = 13;
+        if accounts.len() != ACCOUNT_LEN {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let _token_program_info = next_account_info(account_info_iter)?;
+        let _clock_info = next_account_info(account_info_iter)?;
+        let amm_info = next_account_info(account_info_iter)?;
+
+        let amm = AmmInfo::load_checked(amm_info, program_id)?;
+        let run_crank = AmmStatus::from_u64(amm.status).orderbook_permission()
+            && amm.state != AmmState::IdleState.into_u64();
+        Ok(RunCrankData {
+            status: amm.status,
+            state: amm.state,
+            run_crank,
+        })
+    }
+
+    pub fn process_simulate_info(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        simulate: SimulateInstruction,
+    ) -> ProgramResult {
+        match SimulateParams::from_u64(simulate.param as u64) {
+            SimulateParams::PoolInfo => {
+                let pool_data = Self::simulate_pool_info(program_id, accounts)?;
+                msg!("GetPoolData: {}", pool_data.to_json());
+            }
+            SimulateParams::SwapBaseInInfo => {
+                let swap = simulate
+                    .swap_base_in_value
+                    .ok_or(AmmError::InvalidInput)?;
+                let swap_data = Self::simulate_swap_base_in(program_id, accounts, swap)?;
+                msg!("GetSwapBaseInData: {}", swap_data.to_json());
+            }
+            SimulateParams::SwapBaseOutInfo => {
+                let swap = simulate
+                    .swap_base_out_value
+                    .ok_or(AmmError::InvalidInput)?;
+                let swap_data = Self::simulate_swap_base_out(program_id, accounts, swap)?;
+                msg!("GetSwapBaseOutData: {}", swap_data.to_json());
+            }
+            SimulateParams::RunCrankInfo => {
+                let crank_data = Self::simulate_run_crank(program_id, accounts)?;
+                msg!("RunCrankData: {}", crank_data.to_json());
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        let instruction = AmmInstruction::unpack(input)?;
+        match instruction {
+            AmmInstruction::SwapBaseIn(swap) => {
+                Self::process_swap_base_in(program_id, accounts, swap).map(|_| ())
+            }
+            AmmInstruction::SwapBaseOut(swap) => {
+                Self::process_swap_base_out(program_id, accounts, swap)
+            }
+            AmmInstruction::Deposit(deposit) => {
+                Self::process_deposit(program_id, accounts, deposit)
+            }
+            AmmInstruction::Withdraw(withdraw) => {
+                Self::process_withdraw(program_id, accounts, withdraw)
+            }
+            AmmInstruction::WithdrawPnl => Self::process_withdrawpnl(program_id, accounts),
+            AmmInstruction::SimulateInfo(simulate) => {
+                Self::process_simulate_info(program_id, accounts, simulate)
+            }
+            _ => Err(AmmError::InvalidInstruction.into()),
+        }
+    }
+}
+
+pub mod account_parser {
+    use crate::raydium::state::AmmInfo;
+    use super::*;
+
+    pub struct IdleArgs<'a> {
+        pub program_id: &'a Pubkey,
+        pub total_coin_without_take_pnl: &'a mut u64,
+        pub total_pc_without_take_pnl: &'a mut u64,
+        pub amm: &'a mut AmmInfo,
+        pub bids: &'a Vec<LeafNode>,
+        pub asks: &'a Vec<LeafNode>,
+        pub target: &'a mut TargetOrders,
+    }
+
+    impl<'a> IdleArgs<'a> {
+        pub fn with_parsed_args(
+            program_id: &'a Pubkey,
+            spl_token_program_id: &'a Pubkey,
+            amm: &mut AmmInfo,
+            accounts: &'a [AccountInfo],
+            f: impl FnOnce(IdleArgs) -> ProgramResult,
+        ) -> ProgramResult {
+            if accounts.len() != 11 {
+                return Err(AmmError::WrongAccountsNumber.into());
+            }
+            let &[ref amm_info, ref _market_program_info, ref market_info, ref market_bids_info, ref market_asks_info, ref market_event_queue_info, ref market_authority_info, ref amm_open_orders_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
+                array_ref![accounts, 0, 11];
+
+            let amm_coin_vault =
+                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+            let amm_pc_vault =
+                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+            let mut target =
+                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+
+            let (market_state, open_orders) = Processor::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                market_authority_info,
+                &amm,
+                false,
+            )?;
+            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
+            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
+            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+
+            let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+                Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                    &market_state,
+                    &market_event_queue_info,
+                    &amm_open_orders_info,
+                )?;
+
+            let args = IdleArgs {
+                program_id,
+                total_coin_without_take_pnl: &mut total_coin_without_take_pnl,
+                total_pc_without_take_pnl: &mut total_pc_without_take_pnl,
+                amm,
                 bids: &bids,
                 asks: &asks,
                 target: &mut target,
@@ -1164,3 +2893,816 @@ pub mod account_parser {
     }
 }
 
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bytemuck::{Pod, Zeroable};
+    use serum_dex::fees::FeeTier;
+    use serum_dex::state::AccountFlag;
+    use solana_sdk::account::Account;
+    use solana_sdk::instruction::{AccountMeta, Instruction};
+    use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+    use std::str::FromStr;
+    use crate::account_store::AccountStore;
+    use crate::raydium::client::RAYDIUM_AMM_PROGRAM_ID;
+    use crate::raydium::instruction;
+    use crate::raydium::pool_keys::RaydiumPoolKeys;
+
+    const SERUM_HEAD: &[u8] = b"serum";
+    const SERUM_TAIL: &[u8] = b"padding";
+    /// slab header and room for 16 order book nodes
+    const SLAB_LEN: usize = 32 + 16 * 72;
+
+    pub(crate) const COIN_VAULT_AMOUNT: u64 = 1_000_000_000;
+    pub(crate) const PC_VAULT_AMOUNT: u64 = 2_000_000_000;
+    /// free coin of the amm open orders, held by the market coin vault
+    pub(crate) const OPEN_ORDERS_COIN: u64 = 500_000_000;
+    pub(crate) const NEED_TAKE_PNL_COIN: u64 = 100_000_000;
+    pub(crate) const LP_SUPPLY: u64 = 1_000_000_000;
+    /// balance of each user token account
+    pub(crate) const USER_AMOUNT: u64 = 100_000_000;
+
+    fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount::pack(
+            TokenAccount {
+                mint: *mint,
+                owner: *owner,
+                amount,
+                state: AccountState::Initialized,
+                ..TokenAccount::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+        program_account(&spl_token::id(), data)
+    }
+
+    fn mint_account(mint_authority: &Pubkey, supply: u64) -> Account {
+        let mut data = vec![0; Mint::LEN];
+        Mint::pack(
+            Mint {
+                mint_authority: COption::Some(*mint_authority),
+                supply,
+                decimals: 6,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            },
+            &mut data,
+        )
+        .unwrap();
+        program_account(&spl_token::id(), data)
+    }
+
+    fn program_account(owner: &Pubkey, data: Vec<u8>) -> Account {
+        Account {
+            lamports: 1,
+            data,
+            owner: *owner,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn serum_account(market_program: &Pubkey, state: &[u8]) -> Account {
+        program_account(market_program, [SERUM_HEAD, state, SERUM_TAIL].concat())
+    }
+
+    fn slab_account(market_program: &Pubkey, side: AccountFlag) -> Account {
+        let mut state = (AccountFlag::Initialized as u64 | side as u64).to_le_bytes().to_vec();
+        state.resize(size_of::<u64>() + SLAB_LEN, 0);
+        serum_account(market_program, &state)
+    }
+
+    /// A SwapOnly pool of two 6 decimal mints with its OpenBook market and a user holding
+    /// `USER_AMOUNT` of coin, pc and lp. The pool totals are 1_400_000_000 coin, vault and
+    /// open orders less the pnl owed, against 2_000_000_000 pc.
+    pub(crate) struct PoolFixture {
+        pub keys: RaydiumPoolKeys,
+        pub market_program: Pubkey,
+        pub bids: Pubkey,
+        pub asks: Pubkey,
+        pub event_queue: Pubkey,
+        pub market_coin_vault: Pubkey,
+        pub market_pc_vault: Pubkey,
+        pub vault_signer: Pubkey,
+        pub user: Pubkey,
+        pub user_coin: Pubkey,
+        pub user_pc: Pubkey,
+        pub user_lp: Pubkey,
+        pub store: AccountStore,
+    }
+
+    impl PoolFixture {
+        pub(crate) fn new() -> Self {
+            let program_id = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+            let market_program = config_feature::openbook_program::id();
+            let market = Pubkey::new_unique();
+            let keys = RaydiumPoolKeys::derive(&program_id, &market);
+            let (vault_signer_nonce, vault_signer) = (0..)
+                .find_map(|nonce| {
+                    gen_vault_signer_key(nonce, &market, &market_program)
+                        .ok()
+                        .map(|vault_signer| (nonce, vault_signer))
+                })
+                .unwrap();
+            let coin_mint = Pubkey::new_unique();
+            let pc_mint = Pubkey::new_unique();
+            let bids = Pubkey::new_unique();
+            let asks = Pubkey::new_unique();
+            let event_queue = Pubkey::new_unique();
+            let market_coin_vault = Pubkey::new_unique();
+            let market_pc_vault = Pubkey::new_unique();
+            let user = Pubkey::new_unique();
+            let user_coin = Pubkey::new_unique();
+            let user_pc = Pubkey::new_unique();
+            let user_lp = Pubkey::new_unique();
+
+            let mut amm = AmmInfo::default();
+            amm.status = AmmStatus::SwapOnly.into_u64();
+            amm.nonce = keys.nonce as u64;
+            amm.state = AmmState::IdleState.into_u64();
+            amm.coin_decimals = 6;
+            amm.pc_decimals = 6;
+            amm.sys_decimal_value = 1_000_000;
+            amm.fees.initialize().unwrap();
+            amm.state_data.need_take_pnl_coin = NEED_TAKE_PNL_COIN;
+            amm.coin_vault = keys.coin_vault;
+            amm.pc_vault = keys.pc_vault;
+            amm.coin_vault_mint = coin_mint;
+            amm.pc_vault_mint = pc_mint;
+            amm.lp_mint = keys.lp_mint;
+            amm.open_orders = keys.open_orders;
+            amm.market = market;
+            amm.market_program = market_program;
+            amm.target_orders = keys.target_orders;
+            amm.lp_amount = LP_SUPPLY;
+
+            // k of the current totals, so there is no pnl to take until the target is moved
+            let mut target = TargetOrders::default();
+            target.owner = keys.amm_id.to_aligned_bytes();
+            target.calc_pnl_x = PC_VAULT_AMOUNT as u128;
+            target.calc_pnl_y = (COIN_VAULT_AMOUNT + OPEN_ORDERS_COIN - NEED_TAKE_PNL_COIN) as u128;
+
+            let mut open_orders = OpenOrders::zeroed();
+            open_orders.account_flags = AccountFlag::Initialized as u64 | AccountFlag::OpenOrders as u64;
+            open_orders.market = market.to_aligned_bytes();
+            open_orders.owner = keys.authority.to_aligned_bytes();
+            open_orders.native_coin_free = OPEN_ORDERS_COIN;
+            open_orders.native_coin_total = OPEN_ORDERS_COIN;
+            open_orders.free_slot_bits = u128::MAX;
+
+            let mut market_state = MarketState::zeroed();
+            market_state.account_flags = AccountFlag::Initialized as u64 | AccountFlag::Market as u64;
+            market_state.own_address = market.to_aligned_bytes();
+            market_state.vault_signer_nonce = vault_signer_nonce;
+            market_state.coin_mint = coin_mint.to_aligned_bytes();
+            market_state.pc_mint = pc_mint.to_aligned_bytes();
+            market_state.coin_vault = market_coin_vault.to_aligned_bytes();
+            market_state.coin_deposits_total = OPEN_ORDERS_COIN;
+            market_state.pc_vault = market_pc_vault.to_aligned_bytes();
+            market_state.req_q = Pubkey::new_unique().to_aligned_bytes();
+            market_state.event_q = event_queue.to_aligned_bytes();
+            market_state.bids = bids.to_aligned_bytes();
+            market_state.asks = asks.to_aligned_bytes();
+            market_state.coin_lot_size = 1_000;
+            market_state.pc_lot_size = 10;
+
+            let mut amm_config = AmmConfig::default();
+            amm_config.pnl_owner = user;
+
+            let store = AccountStore::from_accounts(vec![
+                (keys.amm_id, program_account(&program_id, bytemuck::bytes_of(&amm).to_vec())),
+                (keys.target_orders, program_account(&program_id, bytemuck::bytes_of(&target).to_vec())),
+                (keys.amm_config, program_account(&program_id, bytemuck::bytes_of(&amm_config).to_vec())),
+                (keys.open_orders, serum_account(&market_program, bytemuck::bytes_of(&open_orders))),
+                (keys.lp_mint, mint_account(&keys.authority, LP_SUPPLY)),
+                (keys.coin_vault, token_account(&coin_mint, &keys.authority, COIN_VAULT_AMOUNT)),
+                (keys.pc_vault, token_account(&pc_mint, &keys.authority, PC_VAULT_AMOUNT)),
+                (market, serum_account(&market_program, bytemuck::bytes_of(&market_state))),
+                (bids, slab_account(&market_program, AccountFlag::Bids)),
+                (asks, slab_account(&market_program, AccountFlag::Asks)),
+                (market_coin_vault, token_account(&coin_mint, &vault_signer, OPEN_ORDERS_COIN)),
+                (market_pc_vault, token_account(&pc_mint, &vault_signer, 0)),
+                (user_coin, token_account(&coin_mint, &user, USER_AMOUNT)),
+                (user_pc, token_account(&pc_mint, &user, USER_AMOUNT)),
+                (user_lp, token_account(&keys.lp_mint, &user, USER_AMOUNT)),
+            ]);
+            PoolFixture {
+                keys,
+                market_program,
+                bids,
+                asks,
+                event_queue,
+                market_coin_vault,
+                market_pc_vault,
+                vault_signer,
+                user,
+                user_coin,
+                user_pc,
+                user_lp,
+                store,
+            }
+        }
+
+        fn read<T: Pod>(&self, key: &Pubkey, offset: usize) -> T {
+            let data = &self.store.get(key).unwrap().data;
+            bytemuck::pod_read_unaligned(&data[offset..offset + size_of::<T>()])
+        }
+
+        fn write<T: Pod>(&mut self, key: &Pubkey, offset: usize, update: impl FnOnce(&mut T)) {
+            let mut state = self.read::<T>(key, offset);
+            update(&mut state);
+            let mut account = self.store.get(key).unwrap().clone();
+            account.data[offset..offset + size_of::<T>()].copy_from_slice(bytemuck::bytes_of(&state));
+            self.store.insert(*key, account);
+        }
+
+        pub(crate) fn amm(&self) -> AmmInfo {
+            self.read(&self.keys.amm_id, 0)
+        }
+
+        pub(crate) fn update_amm(&mut self, update: impl FnOnce(&mut AmmInfo)) {
+            let key = self.keys.amm_id;
+            self.write(&key, 0, update)
+        }
+
+        pub(crate) fn target(&self) -> TargetOrders {
+            self.read(&self.keys.target_orders, 0)
+        }
+
+        pub(crate) fn update_target(&mut self, update: impl FnOnce(&mut TargetOrders)) {
+            let key = self.keys.target_orders;
+            self.write(&key, 0, update)
+        }
+
+        pub(crate) fn open_orders(&self) -> OpenOrders {
+            self.read(&self.keys.open_orders, SERUM_HEAD.len())
+        }
+
+        fn update_open_orders(&mut self, update: impl FnOnce(&mut OpenOrders)) {
+            let key = self.keys.open_orders;
+            self.write(&key, SERUM_HEAD.len(), update)
+        }
+
+        pub(crate) fn market_state(&self) -> MarketState {
+            self.read(&self.keys.market, SERUM_HEAD.len())
+        }
+
+        fn update_market_state(&mut self, update: impl FnOnce(&mut MarketState)) {
+            let key = self.keys.market;
+            self.write(&key, SERUM_HEAD.len(), update)
+        }
+
+        fn set_token_amount(&mut self, key: &Pubkey, amount: u64) {
+            let mut account = self.store.get(key).unwrap().clone();
+            let mut token_account = TokenAccount::unpack(&account.data).unwrap();
+            token_account.amount = amount;
+            TokenAccount::pack(token_account, &mut account.data).unwrap();
+            self.store.insert(*key, account);
+        }
+
+        /// Rests an amm bid of `quantity` coin lots at `price` in slot 0 of the open orders,
+        /// with its pc locked in the market pc vault. Returns the order id.
+        pub(crate) fn place_bid(&mut self, price: u64, quantity: u64, client_order_id: u64) -> u128 {
+            let order_id = ((price as u128) << 64) | 1;
+            let leaf = LeafNode::new(
+                0,
+                order_id,
+                self.keys.open_orders.to_aligned_bytes(),
+                quantity,
+                FeeTier::Base,
+                client_order_id,
+            );
+            let mut bids = self.store.get(&self.bids).unwrap().clone();
+            let slab_end = bids.data.len() - SERUM_TAIL.len();
+            let slab = Slab::new(&mut bids.data[SERUM_HEAD.len() + size_of::<u64>()..slab_end]);
+            assert!(slab.insert_leaf(&leaf).is_ok());
+            self.store.insert(self.bids, bids);
+
+            let locked_pc = quantity * price * identity(self.market_state().pc_lot_size);
+            self.update_open_orders(|open_orders| {
+                open_orders.orders[0] = order_id;
+                open_orders.client_order_ids[0] = client_order_id;
+                open_orders.free_slot_bits &= !1;
+                open_orders.is_bid_bits |= 1;
+                open_orders.native_pc_total += locked_pc;
+            });
+            self.update_market_state(|market_state| market_state.pc_deposits_total += locked_pc);
+            let market_pc_vault = self.market_pc_vault;
+            let amount = self.token_amount(&market_pc_vault) + locked_pc;
+            self.set_token_amount(&market_pc_vault, amount);
+            order_id
+        }
+
+        fn has_bid(&self, order_id: u128) -> bool {
+            let mut bids = self.store.get(&self.bids).unwrap().data.clone();
+            let slab_end = bids.len() - SERUM_TAIL.len();
+            Slab::new(&mut bids[SERUM_HEAD.len() + size_of::<u64>()..slab_end])
+                .find_by_key(order_id)
+                .is_some()
+        }
+
+        /// Accounts the dex invokers take, in the order of `Invokers::invoke_dex_*` arguments
+        fn dex_instruction(&self) -> Instruction {
+            Instruction {
+                program_id: self.keys.program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(self.market_program, false),
+                    AccountMeta::new(self.keys.market, false),
+                    AccountMeta::new(self.bids, false),
+                    AccountMeta::new(self.asks, false),
+                    AccountMeta::new(self.keys.open_orders, false),
+                    AccountMeta::new_readonly(self.keys.authority, false),
+                    AccountMeta::new(self.market_coin_vault, false),
+                    AccountMeta::new(self.market_pc_vault, false),
+                    AccountMeta::new(self.keys.coin_vault, false),
+                    AccountMeta::new(self.keys.pc_vault, false),
+                    AccountMeta::new_readonly(self.vault_signer, false),
+                    AccountMeta::new_readonly(spl_token::id(), false),
+                ],
+                data: vec![],
+            }
+        }
+
+        pub(crate) fn token_amount(&self, key: &Pubkey) -> u64 {
+            TokenAccount::unpack(&self.store.get(key).unwrap().data).unwrap().amount
+        }
+
+        pub(crate) fn lp_supply(&self) -> u64 {
+            Mint::unpack(&self.store.get(&self.keys.lp_mint).unwrap().data).unwrap().supply
+        }
+
+        fn user_tokens(&self, swap_direction: SwapDirection) -> (Pubkey, Pubkey) {
+            match swap_direction {
+                SwapDirection::Coin2PC => (self.user_coin, self.user_pc),
+                SwapDirection::PC2Coin => (self.user_pc, self.user_coin),
+            }
+        }
+
+        pub(crate) fn swap_base_in(
+            &self,
+            amount_in: u64,
+            minimum_amount_out: u64,
+            swap_direction: SwapDirection,
+        ) -> Instruction {
+            let (source, destination) = self.user_tokens(swap_direction);
+            instruction::swap_base_in(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.authority, &self.keys.open_orders,
+                &self.keys.coin_vault, &self.keys.pc_vault, &self.market_program, &self.keys.market,
+                &self.bids, &self.asks, &self.event_queue, &self.market_coin_vault, &self.market_pc_vault,
+                &self.vault_signer, &source, &destination, &self.user, amount_in, minimum_amount_out,
+            )
+            .unwrap()
+        }
+
+        pub(crate) fn swap_base_out(
+            &self,
+            max_amount_in: u64,
+            amount_out: u64,
+            swap_direction: SwapDirection,
+        ) -> Instruction {
+            let (source, destination) = self.user_tokens(swap_direction);
+            instruction::swap_base_out(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.authority, &self.keys.open_orders,
+                &self.keys.coin_vault, &self.keys.pc_vault, &self.market_program, &self.keys.market,
+                &self.bids, &self.asks, &self.event_queue, &self.market_coin_vault, &self.market_pc_vault,
+                &self.vault_signer, &source, &destination, &self.user, max_amount_in, amount_out,
+            )
+            .unwrap()
+        }
+
+        pub(crate) fn deposit(&self, max_coin_amount: u64, max_pc_amount: u64, base_side: u64) -> Instruction {
+            instruction::deposit(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.authority, &self.keys.open_orders,
+                &self.keys.target_orders, &self.keys.lp_mint, &self.keys.coin_vault, &self.keys.pc_vault,
+                &self.keys.market, &self.event_queue, &self.user_coin, &self.user_pc, &self.user_lp,
+                &self.user, max_coin_amount, max_pc_amount, base_side,
+            )
+            .unwrap()
+        }
+
+        pub(crate) fn withdraw(&self, amount: u64) -> Instruction {
+            instruction::withdraw(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.authority, &self.keys.open_orders,
+                &self.keys.target_orders, &self.keys.lp_mint, &self.keys.coin_vault, &self.keys.pc_vault,
+                &self.market_program, &self.keys.market, &self.market_coin_vault, &self.market_pc_vault,
+                &self.vault_signer, &self.user_lp, &self.user_coin, &self.user_pc, &self.user,
+                &self.event_queue, &self.bids, &self.asks, None, amount,
+            )
+            .unwrap()
+        }
+
+        /// Pnl paid to the user token accounts, signed by `pnl_owner`
+        pub(crate) fn withdrawpnl(&self, pnl_owner: &Pubkey) -> Instruction {
+            instruction::withdrawpnl(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.amm_config, &self.keys.authority,
+                &self.keys.open_orders, &self.keys.coin_vault, &self.keys.pc_vault, &self.user_coin,
+                &self.user_pc, pnl_owner, &self.keys.target_orders, &self.market_program,
+                &self.keys.market, &self.event_queue, &self.market_coin_vault, &self.market_pc_vault,
+                &self.vault_signer,
+            )
+            .unwrap()
+        }
+
+        pub(crate) fn simulate_pool_info(&self) -> Instruction {
+            instruction::simulate_get_pool_info(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.authority, &self.keys.open_orders,
+                &self.keys.coin_vault, &self.keys.pc_vault, &self.keys.lp_mint, &self.keys.market,
+                &self.event_queue, Some(self.keys.target_orders),
+            )
+            .unwrap()
+        }
+
+        pub(crate) fn simulate_swap_base_in(&self, amount_in: u64, swap_direction: SwapDirection) -> Instruction {
+            let (source, destination) = self.user_tokens(swap_direction);
+            instruction::simulate_swap_base_in(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.authority, &self.keys.open_orders,
+                &self.keys.target_orders, &self.keys.coin_vault, &self.keys.pc_vault, &self.keys.lp_mint,
+                &self.market_program, &self.keys.market, &self.event_queue, &source, &destination,
+                &self.user, amount_in,
+            )
+            .unwrap()
+        }
+
+        pub(crate) fn simulate_swap_base_out(&self, amount_out: u64, swap_direction: SwapDirection) -> Instruction {
+            let (source, destination) = self.user_tokens(swap_direction);
+            instruction::simulate_swap_base_out(
+                &self.keys.program_id, &self.keys.amm_id, &self.keys.authority, &self.keys.open_orders,
+                &self.keys.target_orders, &self.keys.coin_vault, &self.keys.pc_vault, &self.keys.lp_mint,
+                &self.market_program, &self.keys.market, &self.event_queue, &source, &destination,
+                &self.user, amount_out,
+            )
+            .unwrap()
+        }
+
+        /// User and amm vault balances, `(user_coin, user_pc, user_lp, coin_vault, pc_vault)`
+        pub(crate) fn balances(&self) -> (u64, u64, u64, u64, u64) {
+            (
+                self.token_amount(&self.user_coin),
+                self.token_amount(&self.user_pc),
+                self.token_amount(&self.user_lp),
+                self.token_amount(&self.keys.coin_vault),
+                self.token_amount(&self.keys.pc_vault),
+            )
+        }
+    }
+
+    fn initial_balances() -> (u64, u64, u64, u64, u64) {
+        (USER_AMOUNT, USER_AMOUNT, USER_AMOUNT, COIN_VAULT_AMOUNT, PC_VAULT_AMOUNT)
+    }
+
+    #[test]
+    fn swap_base_in_returns_the_balances_it_left() {
+        let mut pool = PoolFixture::new();
+        let quote = quote::quote_swap_base_in(
+            &pool.amm(),
+            COIN_VAULT_AMOUNT,
+            PC_VAULT_AMOUNT,
+            &pool.open_orders(),
+            10_000_000,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        let ix = pool.swap_base_in(10_000_000, quote.amount_out, SwapDirection::Coin2PC);
+        let mut result = None;
+        pool.store
+            .execute(&ix, |program_id, accounts, _| {
+                let swap = SwapInstructionBaseIn { amount_in: 10_000_000, minimum_amount_out: quote.amount_out };
+                result = Some(Processor::process_swap_base_in(program_id, accounts, swap)?);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(
+            result,
+            Some(SwapResult {
+                swap_direction: SwapDirection::Coin2PC,
+                amount_in: 10_000_000,
+                amount_out: 14_149_187,
+                swap_fee: 25_000,
+                user_source_amount: USER_AMOUNT - 10_000_000,
+                user_destination_amount: USER_AMOUNT + 14_149_187,
+                amm_coin_vault_amount: COIN_VAULT_AMOUNT + 10_000_000,
+                amm_pc_vault_amount: PC_VAULT_AMOUNT - 14_149_187,
+            })
+        );
+        assert_eq!((quote.amount_out, quote.fee), (14_149_187, 25_000));
+        assert_eq!(
+            pool.balances(),
+            (
+                USER_AMOUNT - 10_000_000,
+                USER_AMOUNT + 14_149_187,
+                USER_AMOUNT,
+                COIN_VAULT_AMOUNT + 10_000_000,
+                PC_VAULT_AMOUNT - 14_149_187,
+            )
+        );
+        let amm = pool.amm();
+        assert_eq!(amm.state_data.swap_coin_in_amount, 10_000_000);
+        assert_eq!(amm.state_data.swap_pc_out_amount, 14_149_187);
+        assert_eq!(amm.state_data.swap_acc_coin_fee, 25_000);
+    }
+
+    #[test]
+    fn swap_base_in_rejects_output_below_minimum() {
+        let mut pool = PoolFixture::new();
+        let ix = pool.swap_base_in(10_000_000, 14_149_188, SwapDirection::Coin2PC);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::ExceededSlippage.into()));
+        assert_eq!(pool.balances(), initial_balances());
+        assert_eq!(pool.amm().state_data.swap_coin_in_amount, 0);
+    }
+
+    #[test]
+    fn swap_base_out_pays_the_quoted_amount_in() {
+        let mut pool = PoolFixture::new();
+        let quote = quote::quote_swap_base_out(
+            &pool.amm(),
+            COIN_VAULT_AMOUNT,
+            PC_VAULT_AMOUNT,
+            &pool.open_orders(),
+            14_149_187,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        assert_eq!((quote.amount_in, quote.fee), (10_000_000, 25_000));
+
+        let ix = pool.swap_base_out(quote.amount_in, 14_149_187, SwapDirection::Coin2PC);
+        pool.store.execute_raydium(&ix).unwrap();
+        assert_eq!(
+            pool.balances(),
+            (
+                USER_AMOUNT - 10_000_000,
+                USER_AMOUNT + 14_149_187,
+                USER_AMOUNT,
+                COIN_VAULT_AMOUNT + 10_000_000,
+                PC_VAULT_AMOUNT - 14_149_187,
+            )
+        );
+        let amm = pool.amm();
+        assert_eq!(amm.state_data.swap_coin_in_amount, 10_000_000);
+        assert_eq!(amm.state_data.swap_pc_out_amount, 14_149_187);
+        assert_eq!(amm.state_data.swap_acc_coin_fee, 25_000);
+    }
+
+    #[test]
+    fn swap_base_out_rejects_slippage_and_draining_the_pool() {
+        let mut pool = PoolFixture::new();
+        let ix = pool.swap_base_out(9_999_999, 14_149_187, SwapDirection::Coin2PC);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::ExceededSlippage.into()));
+        let ix = pool.swap_base_out(u64::MAX, PC_VAULT_AMOUNT, SwapDirection::Coin2PC);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::InsufficientFunds.into()));
+        assert_eq!(pool.balances(), initial_balances());
+        assert_eq!(pool.amm().state_data.swap_coin_in_amount, 0);
+    }
+
+    #[test]
+    fn deposit_mints_lp_at_the_pool_ratio() {
+        let mut pool = PoolFixture::new();
+        // 14_000_000 coin of the 1_400_000_000 in the pool takes 1% of the pc and mints 1% of the lp
+        let ix = pool.deposit(14_000_000, 30_000_000, 0);
+        pool.store.execute_raydium(&ix).unwrap();
+        assert_eq!(
+            pool.balances(),
+            (
+                USER_AMOUNT - 14_000_000,
+                USER_AMOUNT - 20_000_000,
+                USER_AMOUNT + 10_000_000,
+                COIN_VAULT_AMOUNT + 14_000_000,
+                PC_VAULT_AMOUNT + 20_000_000,
+            )
+        );
+        assert_eq!(pool.lp_supply(), LP_SUPPLY + 10_000_000);
+        assert_eq!(pool.amm().lp_amount, LP_SUPPLY + 10_000_000);
+        let target = pool.target();
+        assert_eq!(target.calc_pnl_x, 2_020_000_000);
+        assert_eq!(target.calc_pnl_y, 1_414_000_000);
+    }
+
+    #[test]
+    fn deposit_rejects_slippage_empty_and_unfunded_amounts() {
+        let mut pool = PoolFixture::new();
+        let ix = pool.deposit(14_000_000, 19_999_999, 0);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::ExceededSlippage.into()));
+        let ix = pool.deposit(0, 30_000_000, 0);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::InvalidInput.into()));
+        // 150_000_000 pc needs 105_000_000 coin, more than the user holds
+        let ix = pool.deposit(200_000_000, 150_000_000, 1);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::InsufficientFunds.into()));
+        assert_eq!(pool.balances(), initial_balances());
+        assert_eq!(pool.lp_supply(), LP_SUPPLY);
+    }
+
+    #[test]
+    fn withdraw_burns_lp_for_its_share_of_the_pool() {
+        let mut pool = PoolFixture::new();
+        // a tenth of the lp takes a tenth of the pool totals
+        let ix = pool.withdraw(USER_AMOUNT);
+        pool.store.execute_raydium(&ix).unwrap();
+        assert_eq!(
+            pool.balances(),
+            (
+                USER_AMOUNT + 140_000_000,
+                USER_AMOUNT + 200_000_000,
+                0,
+                COIN_VAULT_AMOUNT - 140_000_000,
+                PC_VAULT_AMOUNT - 200_000_000,
+            )
+        );
+        assert_eq!(pool.lp_supply(), LP_SUPPLY - USER_AMOUNT);
+        assert_eq!(pool.amm().lp_amount, LP_SUPPLY - USER_AMOUNT);
+        let target = pool.target();
+        assert_eq!(target.calc_pnl_x, 1_800_000_000);
+        assert_eq!(target.calc_pnl_y, 1_260_000_000);
+    }
+
+    #[test]
+    fn withdraw_rejects_empty_and_unfunded_amounts() {
+        let mut pool = PoolFixture::new();
+        let ix = pool.withdraw(0);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::InvalidInput.into()));
+        let ix = pool.withdraw(USER_AMOUNT + 1);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::InsufficientFunds.into()));
+        assert_eq!(pool.balances(), initial_balances());
+        assert_eq!(pool.lp_supply(), LP_SUPPLY);
+    }
+
+    #[test]
+    fn withdrawpnl_pays_the_pnl_of_the_k_growth() {
+        let mut pool = PoolFixture::new();
+        // k grew from 1_900_000_000 * 1_330_000_000 at the same price
+        pool.update_target(|target| {
+            target.calc_pnl_x = 1_900_000_000;
+            target.calc_pnl_y = 1_330_000_000;
+        });
+        let ix = pool.withdrawpnl(&pool.user);
+        pool.store.execute_raydium(&ix).unwrap();
+
+        // 12% of the 100_000_000 pc and 70_000_000 coin of growth, with the coin already owed
+        assert_eq!(
+            pool.balances(),
+            (
+                USER_AMOUNT + NEED_TAKE_PNL_COIN + 8_400_000,
+                USER_AMOUNT + 12_000_000,
+                USER_AMOUNT,
+                COIN_VAULT_AMOUNT - NEED_TAKE_PNL_COIN - 8_400_000,
+                PC_VAULT_AMOUNT - 12_000_000,
+            )
+        );
+        let amm = pool.amm();
+        assert_eq!(amm.state_data.need_take_pnl_coin, 0);
+        assert_eq!(amm.state_data.need_take_pnl_pc, 0);
+        assert_eq!(amm.state_data.total_pnl_pc, 100_000_000);
+        assert_eq!(amm.state_data.total_pnl_coin, 70_000_000);
+        let target = pool.target();
+        assert_eq!(target.calc_pnl_x, 1_988_000_000);
+        assert_eq!(target.calc_pnl_y, 1_391_600_000);
+    }
+
+    #[test]
+    fn withdrawpnl_rejects_other_owners_and_no_pnl() {
+        let mut pool = PoolFixture::new();
+        let ix = pool.withdrawpnl(&Pubkey::new_unique());
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::InvalidSignAccount.into()));
+
+        pool.update_amm(|amm| amm.state_data.need_take_pnl_coin = 0);
+        pool.update_target(|target| target.calc_pnl_y = (COIN_VAULT_AMOUNT + OPEN_ORDERS_COIN) as u128);
+        let ix = pool.withdrawpnl(&pool.user);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::TakePnlError.into()));
+        assert_eq!(pool.balances(), initial_balances());
+    }
+
+    #[test]
+    fn cancel_and_settle_move_open_orders_funds_to_the_amm_vaults() {
+        let mut pool = PoolFixture::new();
+        let nonce = pool.keys.nonce;
+        // 10 lots at 100 with a pc lot size of 10
+        let order_id = pool.place_bid(100, 10, 7);
+        assert_eq!(identity(pool.open_orders().native_pc_total), 10_000);
+
+        let ix = pool.dex_instruction();
+        pool.store
+            .execute(&ix, |program_id, accounts, _| {
+                Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                    program_id,
+                    &accounts[0],
+                    &accounts[1],
+                    &accounts[2],
+                    &accounts[3],
+                    &accounts[4],
+                    &accounts[5],
+                    [7, 0, 0, 0, 0, 0, 0, 0],
+                    nonce,
+                )
+            })
+            .unwrap();
+        let open_orders = pool.open_orders();
+        assert_eq!(identity(open_orders.native_pc_free), 10_000);
+        assert_eq!(identity(open_orders.native_coin_free), OPEN_ORDERS_COIN);
+        assert_eq!(identity(open_orders.free_slot_bits), u128::MAX);
+        assert_eq!(identity(open_orders.is_bid_bits), 0);
+        assert!(!pool.has_bid(order_id));
+
+        pool.store
+            .execute(&ix, |program_id, accounts, _| {
+                Invokers::invoke_dex_settle_funds(
+                    program_id,
+                    &accounts[0],
+                    &accounts[1],
+                    &accounts[4],
+                    &accounts[5],
+                    &accounts[6],
+                    &accounts[7],
+                    &accounts[8],
+                    &accounts[9],
+                    &accounts[10],
+                    &accounts[11],
+                    None,
+                    nonce,
+                )
+            })
+            .unwrap();
+        let open_orders = pool.open_orders();
+        assert_eq!(identity(open_orders.native_coin_free), 0);
+        assert_eq!(identity(open_orders.native_pc_free), 0);
+        assert_eq!(identity(open_orders.native_coin_total), 0);
+        assert_eq!(identity(open_orders.native_pc_total), 0);
+        let market_state = pool.market_state();
+        assert_eq!(identity(market_state.coin_deposits_total), 0);
+        assert_eq!(identity(market_state.pc_deposits_total), 0);
+        assert_eq!(pool.token_amount(&pool.market_coin_vault), 0);
+        assert_eq!(pool.token_amount(&pool.market_pc_vault), 0);
+        assert_eq!(pool.token_amount(&pool.keys.coin_vault), COIN_VAULT_AMOUNT + OPEN_ORDERS_COIN);
+        assert_eq!(pool.token_amount(&pool.keys.pc_vault), PC_VAULT_AMOUNT + 10_000);
+    }
+
+    #[test]
+    fn simulate_reports_pool_totals_and_swap_quotes() {
+        let mut pool = PoolFixture::new();
+        let ix = pool.simulate_pool_info();
+        let mut pool_data = None;
+        pool.store
+            .execute(&ix, |program_id, accounts, _| {
+                pool_data = Some(Processor::simulate_pool_info(program_id, accounts)?);
+                Ok(())
+            })
+            .unwrap();
+        let pool_data = pool_data.unwrap();
+        assert_eq!(pool_data.status, AmmStatus::SwapOnly.into_u64());
+        assert_eq!(pool_data.pool_coin_amount, 1_400_000_000);
+        assert_eq!(pool_data.pool_pc_amount, PC_VAULT_AMOUNT);
+        assert_eq!(pool_data.pnl_coin_amount, NEED_TAKE_PNL_COIN);
+        assert_eq!(pool_data.pool_lp_supply, LP_SUPPLY);
+        assert_eq!(pool_data.lp_decimals, 6);
+        // the message only logs, the accounts are left as they were
+        pool.store.execute_raydium(&ix).unwrap();
+
+        let quote = quote::quote_swap_base_in(
+            &pool.amm(),
+            COIN_VAULT_AMOUNT,
+            PC_VAULT_AMOUNT,
+            &pool.open_orders(),
+            10_000_000,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        let ix = pool.simulate_swap_base_in(10_000_000, SwapDirection::Coin2PC);
+        let mut swap_data = None;
+        pool.store
+            .execute(&ix, |program_id, accounts, _| {
+                let swap = SwapInstructionBaseIn { amount_in: 10_000_000, minimum_amount_out: 0 };
+                swap_data = Some(Processor::simulate_swap_base_in(program_id, accounts, swap)?);
+                Ok(())
+            })
+            .unwrap();
+        let swap_data = swap_data.unwrap();
+        assert_eq!(swap_data.minimum_amount_out, 14_149_187);
+        assert_eq!(swap_data.minimum_amount_out, quote.amount_out);
+        assert_eq!(swap_data.price_impact, quote.price_impact);
+
+        let ix = pool.simulate_swap_base_out(14_149_187, SwapDirection::Coin2PC);
+        let mut swap_data = None;
+        pool.store
+            .execute(&ix, |program_id, accounts, _| {
+                let swap = SwapInstructionBaseOut { max_amount_in: 0, amount_out: 14_149_187 };
+                swap_data = Some(Processor::simulate_swap_base_out(program_id, accounts, swap)?);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(swap_data.unwrap().max_amount_in, 10_000_000);
+        assert_eq!(pool.balances(), initial_balances());
+    }
+
+    #[test]
+    fn simulate_rejects_wrong_accounts_and_status() {
+        let mut pool = PoolFixture::new();
+        let mut ix = pool.simulate_swap_base_in(10_000_000, SwapDirection::Coin2PC);
+        ix.accounts.pop();
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::WrongAccountsNumber.into()));
+
+        pool.update_amm(|amm| amm.status = AmmStatus::WithdrawOnly.into_u64());
+        let ix = pool.simulate_swap_base_in(10_000_000, SwapDirection::Coin2PC);
+        assert_eq!(pool.store.execute_raydium(&ix), Err(AmmError::InvalidStatus.into()));
+    }
+}
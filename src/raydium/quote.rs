@@ -0,0 +1,387 @@
+//! Offline swap quotes, computed from a decoded AmmInfo and the pool balances
+
+use crate::raydium::error::AmmError;
+use crate::raydium::math::{Calculator, CheckedCeilDiv, SwapDirection, U128};
+use crate::raydium::state::{AmmInfo, AmmStatus, TEN_THOUSAND};
+use serum_dex::state::OpenOrders;
+
+/// Result of quoting a swap against an amm pool.
+/// All pool amounts are the totals without the pnl waiting to be taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapQuote {
+    pub swap_direction: SwapDirection,
+    /// source token paid by the user, swap fee included
+    pub amount_in: u64,
+    /// destination token received by the user
+    pub amount_out: u64,
+    /// swap fee charged in source token
+    pub fee: u64,
+    /// price impact against the pool price before the swap, TEN_THOUSAND as denominator
+    pub price_impact: u64,
+    /// pool coin amount before the swap
+    pub pool_coin_amount: u64,
+    /// pool pc amount before the swap
+    pub pool_pc_amount: u64,
+    /// pool coin amount after the swap
+    pub post_coin_amount: u64,
+    /// pool pc amount after the swap
+    pub post_pc_amount: u64,
+}
+
+fn check_swap_status(amm: &AmmInfo) -> Result<(), AmmError> {
+    if !AmmStatus::valid_status(amm.status) || !AmmStatus::from_u64(amm.status).swap_permission()
+    {
+        return Err(AmmError::InvalidStatus);
+    }
+    Ok(())
+}
+
+/// Price impact of `amount_out` against the pool price, TEN_THOUSAND as denominator
+fn calc_price_impact(
+    amount_in_after_fee: u64,
+    amount_out: u64,
+    total_pc_without_take_pnl: u64,
+    total_coin_without_take_pnl: u64,
+    swap_direction: SwapDirection,
+) -> Result<u64, AmmError> {
+    let (reserve_in, reserve_out) = match swap_direction {
+        SwapDirection::Coin2PC => (total_coin_without_take_pnl, total_pc_without_take_pnl),
+        SwapDirection::PC2Coin => (total_pc_without_take_pnl, total_coin_without_take_pnl),
+    };
+    let spot_amount_out = U128::from(amount_in_after_fee)
+        .checked_mul(reserve_out.into())
+        .ok_or(AmmError::CheckedMulOverflow)?
+        .checked_div(reserve_in.into())
+        .ok_or(AmmError::CheckedDivOverflow)?;
+    if spot_amount_out.is_zero() {
+        return Ok(0);
+    }
+    let price_impact = spot_amount_out
+        .saturating_sub(amount_out.into())
+        .checked_mul(TEN_THOUSAND.into())
+        .ok_or(AmmError::CheckedMulOverflow)?
+        .checked_div(spot_amount_out)
+        .ok_or(AmmError::CheckedDivOverflow)?;
+    Calculator::to_u64(price_impact.as_u128())
+}
+
+fn post_swap_amounts(
+    amount_in: u64,
+    amount_out: u64,
+    total_pc_without_take_pnl: u64,
+    total_coin_without_take_pnl: u64,
+    swap_direction: SwapDirection,
+) -> Result<(u64, u64), AmmError> {
+    let (post_pc_amount, post_coin_amount) = match swap_direction {
+        SwapDirection::Coin2PC => (
+            total_pc_without_take_pnl
+                .checked_sub(amount_out)
+                .ok_or(AmmError::CheckedSubOverflow)?,
+            total_coin_without_take_pnl
+                .checked_add(amount_in)
+                .ok_or(AmmError::CheckedAddOverflow)?,
+        ),
+        SwapDirection::PC2Coin => (
+            total_pc_without_take_pnl
+                .checked_add(amount_in)
+                .ok_or(AmmError::CheckedAddOverflow)?,
+            total_coin_without_take_pnl
+                .checked_sub(amount_out)
+                .ok_or(AmmError::CheckedSubOverflow)?,
+        ),
+    };
+    Ok((post_pc_amount, post_coin_amount))
+}
+
+/// Quote a swap with a fixed `amount_in`, same math as `Processor::process_swap_base_in`.
+/// `open_orders` is the amm open orders account, the orderbook is not read.
+pub fn quote_swap_base_in(
+    amm: &AmmInfo,
+    coin_vault_amount: u64,
+    pc_vault_amount: u64,
+    open_orders: &OpenOrders,
+    amount_in: u64,
+    swap_direction: SwapDirection,
+) -> Result<SwapQuote, AmmError> {
+    check_swap_status(amm)?;
+    let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+        Calculator::calc_total_without_take_pnl_no_orderbook(
+            pc_vault_amount,
+            coin_vault_amount,
+            open_orders,
+            amm,
+        )?;
+    swap_base_in_without_take_pnl(
+        amm,
+        total_pc_without_take_pnl,
+        total_coin_without_take_pnl,
+        amount_in,
+        swap_direction,
+    )
+}
+
+/// Same as `quote_swap_base_in`, from pool totals that already exclude the pnl
+pub fn swap_base_in_without_take_pnl(
+    amm: &AmmInfo,
+    total_pc_without_take_pnl: u64,
+    total_coin_without_take_pnl: u64,
+    amount_in: u64,
+    swap_direction: SwapDirection,
+) -> Result<SwapQuote, AmmError> {
+    if total_pc_without_take_pnl == 0 || total_coin_without_take_pnl == 0 {
+        return Err(AmmError::CheckedEmptyFunds);
+    }
+
+    let swap_fee = U128::from(amount_in)
+        .checked_mul(amm.fees.swap_fee_numerator.into())
+        .ok_or(AmmError::CheckedMulOverflow)?
+        .checked_ceil_div(amm.fees.swap_fee_denominator.into())
+        .ok_or(AmmError::CheckedDivOverflow)?
+        .0;
+    let swap_in_after_deduct_fee = U128::from(amount_in)
+        .checked_sub(swap_fee)
+        .ok_or(AmmError::CheckedSubOverflow)?;
+    let amount_out = Calculator::to_u64(
+        Calculator::swap_token_amount_base_in(
+            swap_in_after_deduct_fee,
+            total_pc_without_take_pnl.into(),
+            total_coin_without_take_pnl.into(),
+            swap_direction,
+        )
+        .as_u128(),
+    )?;
+    let reserve_out = match swap_direction {
+        SwapDirection::Coin2PC => total_pc_without_take_pnl,
+        SwapDirection::PC2Coin => total_coin_without_take_pnl,
+    };
+    if amount_out >= reserve_out {
+        return Err(AmmError::InsufficientFunds);
+    }
+
+    let price_impact = calc_price_impact(
+        Calculator::to_u64(swap_in_after_deduct_fee.as_u128())?,
+        amount_out,
+        total_pc_without_take_pnl,
+        total_coin_without_take_pnl,
+        swap_direction,
+    )?;
+    let (post_pc_amount, post_coin_amount) = post_swap_amounts(
+        amount_in,
+        amount_out,
+        total_pc_without_take_pnl,
+        total_coin_without_take_pnl,
+        swap_direction,
+    )?;
+
+    Ok(SwapQuote {
+        swap_direction,
+        amount_in,
+        amount_out,
+        fee: Calculator::to_u64(swap_fee.as_u128())?,
+        price_impact,
+        pool_coin_amount: total_coin_without_take_pnl,
+        pool_pc_amount: total_pc_without_take_pnl,
+        post_coin_amount,
+        post_pc_amount,
+    })
+}
+
+/// Quote a swap with a fixed `amount_out`, same math as `Processor::process_swap_base_out`.
+/// `open_orders` is the amm open orders account, the orderbook is not read.
+pub fn quote_swap_base_out(
+    amm: &AmmInfo,
+    coin_vault_amount: u64,
+    pc_vault_amount: u64,
+    open_orders: &OpenOrders,
+    amount_out: u64,
+    swap_direction: SwapDirection,
+) -> Result<SwapQuote, AmmError> {
+    check_swap_status(amm)?;
+    let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+        Calculator::calc_total_without_take_pnl_no_orderbook(
+            pc_vault_amount,
+            coin_vault_amount,
+            open_orders,
+            amm,
+        )?;
+    swap_base_out_without_take_pnl(
+        amm,
+        total_pc_without_take_pnl,
+        total_coin_without_take_pnl,
+        amount_out,
+        swap_direction,
+    )
+}
+
+/// Same as `quote_swap_base_out`, from pool totals that already exclude the pnl
+pub fn swap_base_out_without_take_pnl(
+    amm: &AmmInfo,
+    total_pc_without_take_pnl: u64,
+    total_coin_without_take_pnl: u64,
+    amount_out: u64,
+    swap_direction: SwapDirection,
+) -> Result<SwapQuote, AmmError> {
+    if total_pc_without_take_pnl == 0 || total_coin_without_take_pnl == 0 {
+        return Err(AmmError::CheckedEmptyFunds);
+    }
+    let reserve_out = match swap_direction {
+        SwapDirection::Coin2PC => total_pc_without_take_pnl,
+        SwapDirection::PC2Coin => total_coin_without_take_pnl,
+    };
+    if amount_out >= reserve_out {
+        return Err(AmmError::InsufficientFunds);
+    }
+
+    let swap_in_before_add_fee = Calculator::swap_token_amount_base_out(
+        amount_out.into(),
+        total_pc_without_take_pnl.into(),
+        total_coin_without_take_pnl.into(),
+        swap_direction,
+    );
+    // swap_in_after_add_fee * (1 - 0.0025) = swap_in_before_add_fee
+    // swap_in_after_add_fee = swap_in_before_add_fee / (1 - 0.0025)
+    let swap_in_after_add_fee = swap_in_before_add_fee
+        .checked_mul(amm.fees.swap_fee_denominator.into())
+        .ok_or(AmmError::CheckedMulOverflow)?
+        .checked_ceil_div(
+            amm.fees
+                .swap_fee_denominator
+                .checked_sub(amm.fees.swap_fee_numerator)
+                .ok_or(AmmError::CheckedSubOverflow)?
+                .into(),
+        )
+        .ok_or(AmmError::CheckedDivOverflow)?
+        .0;
+    let amount_in = Calculator::to_u64(swap_in_after_add_fee.as_u128())?;
+    let swap_in_before_add_fee = Calculator::to_u64(swap_in_before_add_fee.as_u128())?;
+    let swap_fee = amount_in
+        .checked_sub(swap_in_before_add_fee)
+        .ok_or(AmmError::CheckedSubOverflow)?;
+
+    let price_impact = calc_price_impact(
+        swap_in_before_add_fee,
+        amount_out,
+        total_pc_without_take_pnl,
+        total_coin_without_take_pnl,
+        swap_direction,
+    )?;
+    let (post_pc_amount, post_coin_amount) = post_swap_amounts(
+        amount_in,
+        amount_out,
+        total_pc_without_take_pnl,
+        total_coin_without_take_pnl,
+        swap_direction,
+    )?;
+
+    Ok(SwapQuote {
+        swap_direction,
+        amount_in,
+        amount_out,
+        fee: swap_fee,
+        price_impact,
+        pool_coin_amount: total_coin_without_take_pnl,
+        pool_pc_amount: total_pc_without_take_pnl,
+        post_coin_amount,
+        post_pc_amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+    use crate::raydium::state::Fees;
+
+    fn pool() -> (AmmInfo, OpenOrders) {
+        let mut amm = AmmInfo::default();
+        amm.status = AmmStatus::SwapOnly as u64;
+        amm.fees = Fees {
+            swap_fee_numerator: 25,
+            swap_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        amm.state_data.need_take_pnl_coin = 100_000_000;
+        let mut open_orders = OpenOrders::zeroed();
+        open_orders.native_coin_total = 500_000_000;
+        (amm, open_orders)
+    }
+
+    #[test]
+    fn swap_base_in_matches_processor_math() {
+        let (amm, open_orders) = pool();
+        let quote = quote_swap_base_in(
+            &amm,
+            1_000_000_000,
+            2_000_000_000,
+            &open_orders,
+            10_000_000,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+
+        // what process_swap_base_in computes for the same accounts
+        let (total_pc, total_coin) = Calculator::calc_total_without_take_pnl_no_orderbook(
+            2_000_000_000,
+            1_000_000_000,
+            &open_orders,
+            &amm,
+        )
+        .unwrap();
+        let swap_fee = U128::from(10_000_000u64)
+            .checked_mul(amm.fees.swap_fee_numerator.into())
+            .unwrap()
+            .checked_ceil_div(amm.fees.swap_fee_denominator.into())
+            .unwrap()
+            .0;
+        let amount_out = Calculator::swap_token_amount_base_in(
+            U128::from(10_000_000u64) - swap_fee,
+            total_pc.into(),
+            total_coin.into(),
+            SwapDirection::Coin2PC,
+        )
+        .as_u64();
+
+        assert_eq!((total_pc, total_coin), (2_000_000_000, 1_400_000_000));
+        assert_eq!(quote.fee, 25_000);
+        assert_eq!(quote.amount_out, amount_out);
+        assert_eq!(quote.amount_out, 14_149_187);
+        assert_eq!(quote.pool_coin_amount, 1_400_000_000);
+        assert_eq!(quote.post_coin_amount, 1_410_000_000);
+        assert_eq!(quote.post_pc_amount, 2_000_000_000 - 14_149_187);
+    }
+
+    #[test]
+    fn swap_base_out_covers_the_amount_out() {
+        let (amm, open_orders) = pool();
+        let quote = quote_swap_base_out(
+            &amm,
+            1_000_000_000,
+            2_000_000_000,
+            &open_orders,
+            14_149_187,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        assert_eq!(quote.amount_in, 10_000_000);
+        assert_eq!(quote.fee, 25_000);
+
+        let base_in = quote_swap_base_in(
+            &amm,
+            1_000_000_000,
+            2_000_000_000,
+            &open_orders,
+            quote.amount_in,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        assert!(base_in.amount_out >= 14_149_187);
+    }
+
+    #[test]
+    fn quote_rejects_pools_without_swap_permission() {
+        let (mut amm, open_orders) = pool();
+        amm.status = AmmStatus::OrderBookOnly as u64;
+        let result = quote_swap_base_in(&amm, 1_000, 1_000, &open_orders, 10, SwapDirection::PC2Coin);
+        assert_eq!(result, Err(AmmError::InvalidStatus));
+    }
+}
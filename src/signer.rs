@@ -0,0 +1,247 @@
+//! Named signing keys loaded from keypair files, environment variables or encrypted keystores.
+//! Secret material is never logged or returned, only names and pubkeys are.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rocket::config::ConfigError;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair, read_keypair_file, Keypair, Signer};
+use crate::error::Error;
+
+/// Signer the routes use when a request names none
+pub const DEFAULT_SIGNER: &str = "default";
+
+/// Keypair encrypted with a key derived from a password by scrypt
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub pubkey: String,
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// base64
+    pub salt: String,
+    /// base64
+    pub nonce: String,
+    /// base64 ChaCha20-Poly1305 ciphertext of the 64 keypair bytes
+    pub ciphertext: String,
+}
+
+impl Keystore {
+    const LOG_N: u8 = 15;
+    const R: u32 = 8;
+    const P: u32 = 1;
+
+    fn cipher(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<ChaCha20Poly1305> {
+        let params = scrypt::Params::new(log_n, r, p, 32).map_err(|e| anyhow!("scrypt params: {}", e))?;
+        let mut key = [0u8; 32];
+        scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+            .map_err(|e| anyhow!("scrypt: {}", e))?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    pub fn encrypt(keypair: &Keypair, password: &str) -> Result<Self> {
+        Self::encrypt_with(keypair, password, Self::LOG_N, Self::R, Self::P)
+    }
+
+    fn encrypt_with(keypair: &Keypair, password: &str, log_n: u8, r: u32, p: u32) -> Result<Self> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let cipher = Self::cipher(password, &salt, log_n, r, p)?;
+        let ciphertext = cipher
+            .encrypt(&nonce, keypair.to_bytes().as_ref())
+            .map_err(|_| anyhow!("keystore encryption failed"))?;
+        Ok(Keystore {
+            pubkey: keypair.pubkey().to_string(),
+            log_n,
+            r,
+            p,
+            salt: base64::encode(salt),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<Keypair> {
+        let salt = base64::decode(&self.salt)?;
+        let nonce = base64::decode(&self.nonce)?;
+        let ciphertext = base64::decode(&self.ciphertext)?;
+        if nonce.len() != 12 {
+            return Err(anyhow!("keystore {} has an invalid nonce", self.pubkey));
+        }
+        let cipher = Self::cipher(password, &salt, self.log_n, self.r, self.p)?;
+        let bytes = cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| anyhow!("wrong password for keystore {}", self.pubkey))?;
+        let keypair = Keypair::from_bytes(&bytes).map_err(|_| anyhow!("keystore {} is corrupt", self.pubkey))?;
+        if keypair.pubkey().to_string() != self.pubkey {
+            return Err(anyhow!("keystore {} holds another key", self.pubkey));
+        }
+        Ok(keypair)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Environment variable `encrypt_keystore` reads the password from
+pub const KEYSTORE_PASSWORD_ENV: &str = "KEYSTORE_PASSWORD";
+
+/// `sol-tool encrypt-keystore <keypair file> <keystore file>`: encrypts a CLI keypair file into
+/// a keystore for the `signers` table. The password comes from `KEYSTORE_PASSWORD`, not the
+/// arguments, so it doesn't show up in the process list.
+pub fn encrypt_keystore(args: &[String]) -> Result<Pubkey> {
+    let (keypair_path, keystore_path) = match args {
+        [keypair_path, keystore_path] => (keypair_path, keystore_path),
+        _ => return Err(anyhow!("usage: encrypt-keystore <keypair file> <keystore file>")),
+    };
+    let password = env::var(KEYSTORE_PASSWORD_ENV)
+        .map_err(|_| anyhow!("environment variable {} is not set", KEYSTORE_PASSWORD_ENV))?;
+    let keypair = read_keypair_file(keypair_path)
+        .map_err(|_| anyhow!("can't read keypair file {}", keypair_path))?;
+    Keystore::encrypt(&keypair, &password)?.save(keystore_path)?;
+    Ok(keypair.pubkey())
+}
+
+/// Keypair from an environment variable holding a base58 secret key or a CLI style json array.
+/// Errors name the variable, never its value.
+pub fn keypair_from_env(var: &str) -> Result<Keypair> {
+    let value = env::var(var).map_err(|_| anyhow!("environment variable {} is not set", var))?;
+    let value = value.trim();
+    if value.starts_with('[') {
+        read_keypair(&mut value.as_bytes()).map_err(|_| anyhow!("{} is not a keypair json array", var))
+    } else {
+        let bytes = bs58::decode(value)
+            .into_vec()
+            .map_err(|_| anyhow!("{} is not a base58 secret key", var))?;
+        Keypair::from_bytes(&bytes).map_err(|_| anyhow!("{} is not a base58 secret key", var))
+    }
+}
+
+/// Signing keys by name, from the `signers` table of Rocket.toml:
+///
+/// ```toml
+/// [global.signers.default]
+/// file = "/path/to/id.json"
+/// [global.signers.bot]
+/// env = "BOT_KEYPAIR"
+/// [global.signers.hot]
+/// keystore = "/path/to/hot.keystore"
+/// password_env = "HOT_KEYSTORE_PASSWORD"
+/// ```
+///
+/// Keystores are written by `sol-tool encrypt-keystore`, see `encrypt_keystore`.
+#[derive(Default)]
+pub struct SignerStore {
+    signers: HashMap<String, Keypair>,
+}
+
+impl SignerStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, keypair: Keypair) {
+        self.signers.insert(name.into(), keypair);
+    }
+
+    pub fn from_config(config: &rocket::Config) -> Result<Self> {
+        let mut store = SignerStore::new();
+        let table = match config.get_table("signers") {
+            Ok(table) => table,
+            Err(ConfigError::Missing(_)) => return Ok(store),
+            Err(e) => return Err(anyhow!("invalid signers: {}", e)),
+        };
+        for (name, source) in table.iter() {
+            let get = |key: &str| source.get(key).and_then(|value| value.as_str());
+            let keypair = if let Some(path) = get("file") {
+                read_keypair_file(path).map_err(|_| anyhow!("signer {}: can't read keypair file {}", name, path))?
+            } else if let Some(var) = get("env") {
+                keypair_from_env(var).map_err(|e| anyhow!("signer {}: {}", name, e))?
+            } else if let Some(path) = get("keystore") {
+                let password_var = get("password_env")
+                    .ok_or_else(|| anyhow!("signer {}: keystore needs password_env", name))?;
+                let password = env::var(password_var)
+                    .map_err(|_| anyhow!("signer {}: {} is not set", name, password_var))?;
+                Keystore::load(path)?
+                    .decrypt(&password)
+                    .map_err(|e| anyhow!("signer {}: {}", name, e))?
+            } else {
+                return Err(anyhow!("signer {} needs one of file, env or keystore", name));
+            };
+            store.insert(name.clone(), keypair);
+        }
+        Ok(store)
+    }
+
+    pub fn get(&self, name: &str) -> std::result::Result<&Keypair, Error> {
+        self.signers
+            .get(name)
+            .ok_or_else(|| Error::UnknownSigner(name.to_string()))
+    }
+
+    /// Names and pubkeys, sorted by name
+    pub fn pubkeys(&self) -> Vec<(String, Pubkey)> {
+        let mut pubkeys: Vec<(String, Pubkey)> = self
+            .signers
+            .iter()
+            .map(|(name, keypair)| (name.clone(), keypair.pubkey()))
+            .collect();
+        pubkeys.sort();
+        pubkeys
+    }
+}
+
+impl fmt::Debug for SignerStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.pubkeys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// cheap scrypt parameters, the defaults take seconds in a debug build
+    fn keystore(keypair: &Keypair, password: &str) -> Keystore {
+        Keystore::encrypt_with(keypair, password, 4, 8, 1).unwrap()
+    }
+
+    #[test]
+    fn keystore_round_trips_the_keypair() {
+        let keypair = Keypair::new();
+        let keystore = keystore(&keypair, "correct horse");
+        assert_eq!(keystore.pubkey, keypair.pubkey().to_string());
+        let decrypted = keystore.decrypt("correct horse").unwrap();
+        assert_eq!(decrypted.to_bytes(), keypair.to_bytes());
+    }
+
+    #[test]
+    fn keystore_rejects_wrong_password() {
+        let keystore = keystore(&Keypair::new(), "correct horse");
+        assert!(keystore.decrypt("battery staple").is_err());
+    }
+
+    #[test]
+    fn keystore_rejects_tampered_ciphertext() {
+        let mut keystore = keystore(&Keypair::new(), "correct horse");
+        let mut ciphertext = base64::decode(&keystore.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        keystore.ciphertext = base64::encode(ciphertext);
+        assert!(keystore.decrypt("correct horse").is_err());
+    }
+}
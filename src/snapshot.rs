@@ -0,0 +1,208 @@
+//! Account snapshots recorded from a cluster and replayed offline
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::RpcClient;
+use solana_program::clock::Clock;
+use solana_program::entrypoint::SUCCESS;
+use solana_program::program_pack::Pack;
+use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+use solana_program::pubkey::Pubkey;
+use solana_program::sysvar;
+use solana_sdk::account::Account;
+use crate::account_store::{AccountStore, MAX_MULTIPLE_ACCOUNTS};
+use crate::solfi::{MarketAccount, SwapQuote};
+
+/// One account of a snapshot, data is base64
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotAccount {
+    pub pubkey: String,
+    pub lamports: u64,
+    pub data: String,
+    pub owner: String,
+    pub executable: bool,
+    pub rent_epoch: u64,
+}
+
+impl SnapshotAccount {
+    pub fn new(pubkey: &Pubkey, account: &Account) -> Self {
+        SnapshotAccount {
+            pubkey: pubkey.to_string(),
+            lamports: account.lamports,
+            data: base64::encode(&account.data),
+            owner: account.owner.to_string(),
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+        }
+    }
+
+    pub fn to_account(&self) -> Result<(Pubkey, Account)> {
+        let account = Account {
+            lamports: self.lamports,
+            data: base64::decode(&self.data)?,
+            owner: Pubkey::from_str(&self.owner)?,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+        };
+        Ok((Pubkey::from_str(&self.pubkey)?, account))
+    }
+}
+
+/// Accounts with the slot and clock they were read at
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub slot: u64,
+    pub clock: Clock,
+    pub accounts: Vec<SnapshotAccount>,
+}
+
+impl Snapshot {
+    /// Reads `keys` and the clock sysvar from the cluster, keys without an account are left out.
+    /// The slot is the lowest context slot of the requests.
+    pub fn record(client: &RpcClient, keys: &[Pubkey]) -> Result<Self> {
+        let mut request_keys = vec![sysvar::clock::id()];
+        request_keys.extend_from_slice(keys);
+
+        let mut slot = u64::MAX;
+        let mut clock = None;
+        let mut accounts = Vec::new();
+        for chunk in request_keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let response =
+                client.get_multiple_accounts_with_commitment(chunk, client.commitment())?;
+            slot = slot.min(response.context.slot);
+            for (key, account) in chunk.iter().zip(response.value) {
+                let account = match account {
+                    Some(account) => account,
+                    None => continue,
+                };
+                if *key == sysvar::clock::id() && clock.is_none() {
+                    clock = Some(bincode::deserialize::<Clock>(&account.data)?);
+                } else {
+                    accounts.push(SnapshotAccount::new(key, &account));
+                }
+            }
+        }
+        let clock = clock.ok_or_else(|| anyhow!("clock sysvar not returned"))?;
+        Ok(Snapshot {
+            slot,
+            clock,
+            accounts,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn get(&self, key: &Pubkey) -> Result<Account> {
+        let pubkey = key.to_string();
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.pubkey == pubkey)
+            .ok_or_else(|| anyhow!("account {} not in snapshot", key))?;
+        Ok(account.to_account()?.1)
+    }
+
+    pub fn account_store(&self) -> Result<AccountStore> {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|account| account.to_account())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(AccountStore::from_accounts(accounts))
+    }
+
+    /// Makes `Clock::get` return the recorded clock, so the Raydium processor sees the
+    /// pool open and orderbook times as they were when the snapshot was taken
+    pub fn install_clock(&self) {
+        set_syscall_stubs(Box::new(ClockStubs {
+            clock: self.clock.clone(),
+        }));
+    }
+
+    /// SolFi quote from the recorded market and vault accounts at the recorded slot
+    pub fn solfi_swap_quote(
+        &self,
+        market: &Pubkey,
+        amount_in: u64,
+        is_quote_to_base: bool,
+    ) -> Result<SwapQuote> {
+        let market_account = self.get(market)?;
+        let market_account = *MarketAccount::load(&market_account.data)?;
+        let base_vault = self.get(&market_account.base_vault)?;
+        let quote_vault = self.get(&market_account.quote_vault)?;
+        let base_vault_amount = spl_token::state::Account::unpack(&base_vault.data)?.amount;
+        let quote_vault_amount = spl_token::state::Account::unpack(&quote_vault.data)?.amount;
+        Ok(market_account.swap_quote(
+            amount_in,
+            is_quote_to_base,
+            self.slot,
+            base_vault_amount,
+            quote_vault_amount,
+        )?)
+    }
+}
+
+struct ClockStubs {
+    clock: Clock,
+}
+
+impl SyscallStubs for ClockStubs {
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe {
+            *(var_addr as *mut Clock) = self.clock.clone();
+        }
+        SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOLFI_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/solfi_market.json");
+    const SOLFI_MARKET: &str = "BkeWFSYngm1VTtrSQmrkmnhDD94EWa65xKGnCpKcQpj";
+
+    #[test]
+    fn solfi_swap_quote_replays_fixture() {
+        let snapshot = Snapshot::load(SOLFI_FIXTURE).unwrap();
+        let market = Pubkey::from_str(SOLFI_MARKET).unwrap();
+
+        // 1 base atom in, vaults hold less quote than base so the price retreats 25 bips
+        let quote = snapshot.solfi_swap_quote(&market, 1_000_000_000, false).unwrap();
+        assert_eq!(quote.fair_price_quote_atoms_per_base_atom, 150_000_000);
+        assert_eq!(quote.fair_with_retreat_quote_atoms_per_base_atom, 149_625_000);
+        assert_eq!(quote.fair_amount_out, 149_625_000);
+        assert_eq!(quote.size_edge_milli_bips, 159_850);
+        assert_eq!(quote.time_edge_milli_mult, 1200);
+        assert_eq!(quote.vol_edge_milli_mult, 1000);
+        assert_eq!(quote.edge_milli_bips, 191);
+        assert_eq!(quote.amount_out, 149_622_142);
+
+        let quote = snapshot.solfi_swap_quote(&market, 150_000_000, true).unwrap();
+        assert_eq!(quote.fair_amount_out, 1_002_506_265);
+        assert_eq!(quote.effective_quote_amount, 150_000_000);
+        assert_eq!(quote.amount_out, 1_002_487_016);
+    }
+
+    #[test]
+    fn snapshot_round_trips_accounts() {
+        let snapshot = Snapshot::load(SOLFI_FIXTURE).unwrap();
+        let store = snapshot.account_store().unwrap();
+        for account in &snapshot.accounts {
+            let (pubkey, expected) = account.to_account().unwrap();
+            assert_eq!(store.get(&pubkey), Some(&expected));
+        }
+    }
+}
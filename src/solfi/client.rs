@@ -10,7 +10,7 @@ use solana_client::{
 use solana_sdk::pubkey::Pubkey;
 use uint::core_::mem::size_of;
 
-const SOLFI_PROGRAM_ID: &str = "SoLFiHG9TfgtdUXUjWAxi3LtvYuFyDLVhBWxdMZxyCe";
+pub const SOLFI_PROGRAM_ID: &str = "SoLFiHG9TfgtdUXUjWAxi3LtvYuFyDLVhBWxdMZxyCe";
 
 pub fn fetch_all_market_accounts(rpc_client: &RpcClient) -> Result<Vec<(Pubkey, MarketAccount)>> {
     let market_accounts = rpc_client.get_program_accounts_with_config(
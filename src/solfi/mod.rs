@@ -23,6 +23,16 @@ pub struct MarketConfig {
     pub _padding_1: [u64; 16],
 }
 
+impl MarketConfig {
+    /// `Spline::eval` and the `swap_amount_in` search need both edge splines to increase
+    pub fn check_splines(&self) -> Result<(), ProgramError> {
+        self.size_edge_spline
+            .is_valid(u64::MAX)
+            .and_then(|_| self.time_edge_spline.is_valid(u64::MAX))
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
 #[derive(Default, Debug, Copy, Clone, Zeroable, Pod)]
 #[repr(C)]
 pub struct MarketPrice {
@@ -155,6 +165,26 @@ impl MarketAccount {
         base_vault_amount: u64,
         quote_vault_amount: u64,
     ) -> Result<u64, ProgramError> {
+        self.swap_quote(
+            amount_in,
+            is_quote_to_base,
+            slot,
+            base_vault_amount,
+            quote_vault_amount,
+        )
+        .map(|quote| quote.amount_out)
+    }
+
+    pub fn swap_quote(
+        &self,
+        amount_in: u64,
+        is_quote_to_base: bool,
+        slot: u64,
+        base_vault_amount: u64,
+        quote_vault_amount: u64,
+    ) -> Result<SwapQuote, ProgramError> {
+        self.market_config.check_splines()?;
+
         // Get the fair price with inventory retreat
         let fair_with_retreat =
             self.get_fair_with_inventory_retreat(base_vault_amount, quote_vault_amount)?;
@@ -214,10 +244,138 @@ impl MarketAccount {
             amount_out as u64
         };
 
-        Ok(amount_out_u64)
+        Ok(SwapQuote {
+            fair_price_quote_atoms_per_base_atom: self.market_price.price_quote_atoms_per_base_atom,
+            fair_with_retreat_quote_atoms_per_base_atom: fair_with_retreat,
+            fair_amount_out,
+            effective_quote_amount,
+            size_edge_milli_bips: size_edge_milli,
+            time_edge_milli_mult,
+            vol_edge_milli_mult,
+            edge_milli_bips: edge_milli_bips as u64,
+            amount_out: amount_out_u64,
+        })
+    }
+
+    /// Minimal amount in for which `swap_amount_out` returns at least `amount_out`.
+    ///
+    /// The amount out only passes the target when the fair amount out covers the target
+    /// grossed up by the edge. The fair amount out is non-decreasing in the amount in, and
+    /// so is the edge as the size edge spline increases, which is checked up front.
+    /// Starting from the smallest input whose fair amount out reaches the target, every step
+    /// moves to the smallest input that could cover the edge seen at the current one, so
+    /// every skipped input is too small.
+    pub fn swap_amount_in(
+        &self,
+        amount_out: u64,
+        is_quote_to_base: bool,
+        slot: u64,
+        base_vault_amount: u64,
+        quote_vault_amount: u64,
+    ) -> Result<u64, ProgramError> {
+        if amount_out == 0 {
+            return Ok(0);
+        }
+        let vault_amount_out = if is_quote_to_base {
+            base_vault_amount
+        } else {
+            quote_vault_amount
+        };
+        if amount_out > vault_amount_out {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        // a size edge that drops with size could let a skipped input pass
+        self.market_config.check_splines()?;
+
+        let fair_with_retreat =
+            self.get_fair_with_inventory_retreat(base_vault_amount, quote_vault_amount)?;
+        // Smallest amount in whose fair amount out (retreat, no edge) reaches `fair_amount_out`
+        let min_amount_in_for_fair = |fair_amount_out: u64| -> Result<u64, ProgramError> {
+            // an overflowing conversion is past anything a u64 can hold
+            let reaches = |amount_in: u64| {
+                self.market_price
+                    .swap_fair_price_conversion(amount_in, is_quote_to_base, Some(fair_with_retreat))
+                    .map_or(true, |out| out >= fair_amount_out)
+            };
+            if !reaches(u64::MAX) {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            let (mut lo, mut hi) = (0u64, u64::MAX);
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if reaches(mid) {
+                    hi = mid;
+                } else {
+                    lo = mid + 1;
+                }
+            }
+            Ok(lo)
+        };
+
+        // The edge is never negative, so nothing below this can reach the target
+        let mut amount_in = min_amount_in_for_fair(amount_out)?;
+        loop {
+            let quote = self.swap_quote(
+                amount_in,
+                is_quote_to_base,
+                slot,
+                base_vault_amount,
+                quote_vault_amount,
+            )?;
+            let amount_out_milli_bips = MILLI_BIPS_SCALE
+                .checked_sub(quote.edge_milli_bips as u128)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            if amount_out_milli_bips == 0 {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            // floor(fair * amount_out_milli_bips / MILLI_BIPS_SCALE) >= amount_out
+            // <=> fair >= ceil(amount_out * MILLI_BIPS_SCALE / amount_out_milli_bips)
+            let fair_amount_out_needed = (amount_out as u128)
+                .checked_mul(MILLI_BIPS_SCALE)
+                .and_then(|x| x.checked_add(amount_out_milli_bips - 1))
+                .and_then(|x| x.checked_div(amount_out_milli_bips))
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            let fair_amount_out_needed: u64 = fair_amount_out_needed
+                .try_into()
+                .map_err(|_| ProgramError::InsufficientFunds)?;
+
+            // Inputs below `next_amount_in` miss the target even at the current edge,
+            // and the edge only grows with the amount in.
+            let next_amount_in = min_amount_in_for_fair(fair_amount_out_needed)?;
+            if next_amount_in <= amount_in {
+                if quote.amount_out < amount_out {
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                return Ok(amount_in);
+            }
+            amount_in = next_amount_in;
+        }
     }
 }
 
+/// Breakdown of a quote from `MarketAccount::swap_quote`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SwapQuote {
+    /// Oracle price, before inventory retreat
+    pub fair_price_quote_atoms_per_base_atom: u64,
+    /// Oracle price adjusted by the inventory retreat
+    pub fair_with_retreat_quote_atoms_per_base_atom: u64,
+    /// Amount out at the retreat adjusted price, before edge
+    pub fair_amount_out: u64,
+    /// Swap size in quote atoms, used for the size edge
+    pub effective_quote_amount: u64,
+    /// Size edge from the size edge spline
+    pub size_edge_milli_bips: u64,
+    /// Time edge multiplier, 1000 = 1x
+    pub time_edge_milli_mult: u64,
+    /// Volatility edge multiplier, 1000 = 1x
+    pub vol_edge_milli_mult: u64,
+    /// Combined edge taken from the fair amount out
+    pub edge_milli_bips: u64,
+    /// Final amount out
+    pub amount_out: u64,
+}
+
 #[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
 #[repr(C)]
 pub struct Spline {
@@ -255,6 +413,8 @@ impl Spline {
         Ok(())
     }
 
+    /// Value at `x`, interpolated between the points around it.
+    /// The spline must pass `is_valid`, see `MarketConfig::check_splines`.
     pub fn eval(&self, x: u64) -> u64 {
         if self.len == 0 {
             return 0;
@@ -274,12 +434,12 @@ impl Spline {
 
             if x < x2 {
                 // Perform linear interpolation using integer arithmetic
-                let dx = x2 - x1;
-                let dy = y2 - y1;
-                let offset = x - x1;
+                let dx = (x2 - x1) as u128;
+                let dy = (y2 - y1) as u128;
+                let offset = (x - x1) as u128;
 
-                // Use integer division with rounding
-                return y1 + (dy * offset + dx / 2) / dx;
+                // Use integer division with rounding, the result is at most y2
+                return y1 + ((dy * offset + dx / 2) / dx) as u64;
             }
         }
 
@@ -289,3 +449,141 @@ impl Spline {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOT: u64 = 1_000;
+    const VAULT_AMOUNT: u64 = 10_000;
+
+    /// 2 quote atoms per base atom, no retreat, up to 10% size edge at 1000 quote atoms
+    fn market() -> MarketAccount {
+        let mut market = MarketAccount::zeroed();
+        market.market_price.price_quote_atoms_per_base_atom = 2;
+        market.market_price.price_updated_slot = SLOT;
+        market.market_config.retreat_quote_amount = 1;
+        market.market_config.size_edge_spline = Spline {
+            x: [0, 100, 1_000, 0, 0, 0, 0, 0],
+            y: [0, 200_000_000, 1_000_000_000, 0, 0, 0, 0, 0],
+            len: 3,
+        };
+        market.market_config.time_edge_spline = Spline {
+            x: [0; 8],
+            y: [1000, 0, 0, 0, 0, 0, 0, 0],
+            len: 1,
+        };
+        market
+    }
+
+    #[test]
+    fn swap_amount_in_matches_brute_force_minimum() {
+        let market = market();
+        for is_quote_to_base in [false, true] {
+            let amount_out_of = |amount_in: u64| {
+                market
+                    .swap_amount_out(amount_in, is_quote_to_base, SLOT, VAULT_AMOUNT, VAULT_AMOUNT)
+                    .unwrap()
+            };
+            // the minimal amount in never drops as the target grows, so the scan resumes
+            let mut brute_force = 0;
+            for amount_out in 1..=800 {
+                while amount_out_of(brute_force) < amount_out {
+                    brute_force += 1;
+                }
+                let amount_in = market
+                    .swap_amount_in(amount_out, is_quote_to_base, SLOT, VAULT_AMOUNT, VAULT_AMOUNT)
+                    .unwrap();
+                assert_eq!(amount_in, brute_force, "amount out {} quote to base {}", amount_out, is_quote_to_base);
+            }
+        }
+    }
+
+    #[test]
+    fn swap_quote_breakdown_matches_swap_amount_out() {
+        let mut market = market();
+        market.market_config.time_edge_spline = Spline {
+            x: [0, 10, 0, 0, 0, 0, 0, 0],
+            y: [1000, 2000, 0, 0, 0, 0, 0, 0],
+            len: 2,
+        };
+        market.market_price.volatility_milli_scale = 2000;
+        // 5 slots stale, halfway up the time edge spline
+        let slot = SLOT + 5;
+
+        let quote = market.swap_quote(500, true, slot, VAULT_AMOUNT, VAULT_AMOUNT).unwrap();
+        assert_eq!(
+            quote,
+            SwapQuote {
+                fair_price_quote_atoms_per_base_atom: 2,
+                fair_with_retreat_quote_atoms_per_base_atom: 2,
+                fair_amount_out: 250,
+                effective_quote_amount: 500,
+                size_edge_milli_bips: 555_555_556,
+                time_edge_milli_mult: 1500,
+                vol_edge_milli_mult: 2000,
+                edge_milli_bips: 1_666_666,
+                amount_out: 208,
+            }
+        );
+
+        let quote = market.swap_quote(100, false, slot, VAULT_AMOUNT, VAULT_AMOUNT).unwrap();
+        assert_eq!(quote.fair_amount_out, 200);
+        assert_eq!(quote.effective_quote_amount, 200);
+        assert_eq!(quote.size_edge_milli_bips, 288_888_889);
+        assert_eq!(quote.edge_milli_bips, 866_666);
+        assert_eq!(quote.amount_out, 182);
+
+        for is_quote_to_base in [false, true] {
+            for amount_in in [0, 1, 99, 100, 101, 777, 1_000, 5_000] {
+                let quote = market
+                    .swap_quote(amount_in, is_quote_to_base, slot, VAULT_AMOUNT, VAULT_AMOUNT)
+                    .unwrap();
+                let amount_out = market
+                    .swap_amount_out(amount_in, is_quote_to_base, slot, VAULT_AMOUNT, VAULT_AMOUNT)
+                    .unwrap();
+                assert_eq!(quote.amount_out, amount_out);
+                // the edge is all that separates the fair amount out from the amount out
+                assert_eq!(
+                    quote.amount_out as u128,
+                    quote.fair_amount_out as u128 * (MILLI_BIPS_SCALE - quote.edge_milli_bips as u128)
+                        / MILLI_BIPS_SCALE
+                );
+                assert_eq!(
+                    quote.edge_milli_bips as u128,
+                    quote.size_edge_milli_bips as u128
+                        * quote.time_edge_milli_mult as u128
+                        * quote.vol_edge_milli_mult as u128
+                        / 1_000_000_000
+                );
+                assert_eq!(
+                    quote.size_edge_milli_bips,
+                    market.market_config.size_edge_spline.eval(quote.effective_quote_amount)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decreasing_size_edge_is_an_error() {
+        let mut market = market();
+        market.market_config.size_edge_spline.y[2] = 100_000_000;
+        assert_eq!(
+            market.swap_quote(500, true, SLOT, VAULT_AMOUNT, VAULT_AMOUNT),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            market.swap_amount_in(10, true, SLOT, VAULT_AMOUNT, VAULT_AMOUNT),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn spline_eval_rounds_between_points() {
+        let spline = market().market_config.size_edge_spline;
+        assert_eq!(spline.eval(0), 0);
+        assert_eq!(spline.eval(50), 100_000_000);
+        assert_eq!(spline.eval(550), 600_000_000);
+        assert_eq!(spline.eval(5_000), 1_000_000_000);
+    }
+}
@@ -0,0 +1,386 @@
+//! Swap records of confirmed transactions against Raydium amm pools and SolFi markets.
+//! Amounts are what the pool vaults received and paid, read from the pre and post token
+//! balances, so a pool swapped against twice in one transaction reports the net of both,
+//! in the net direction and without the limits of either instruction.
+
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionTokenBalance};
+use crate::error::{Error, Result};
+use crate::raydium::client::PoolIndex;
+use crate::raydium::decode::{self, DecodedInstruction};
+use crate::raydium::instruction::AmmInstruction;
+use crate::raydium::math::SwapDirection;
+use crate::solfi::client::SOLFI_PROGRAM_ID;
+use crate::solfi::MarketAccount;
+
+const BPS: i128 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Venue {
+    Raydium,
+    Solfi,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapRecord {
+    pub signature: Signature,
+    pub slot: u64,
+    pub venue: Venue,
+    /// amm id or SolFi market
+    pub pool: Pubkey,
+    pub wallet: Pubkey,
+    /// SolFi base is the coin and quote the pc. Net over every swap against the pool in the
+    /// transaction, opposite swaps can make it differ from an instruction's own direction.
+    pub direction: SwapDirection,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// swap fee in the input token on the realized amount in, unknown without the pool
+    /// state and not charged separately by SolFi
+    pub fee: Option<u64>,
+    /// pc per coin in ui units
+    pub effective_price: f64,
+    pub minimum_amount_out: Option<u64>,
+    pub maximum_amount_in: Option<u64>,
+    /// how far the realized amounts beat the instruction limit, in bps of the limit
+    pub slippage_bps: Option<i64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TokenBalance {
+    mint: Pubkey,
+    decimals: u8,
+    pre: u64,
+    post: u64,
+}
+
+fn token_balances(tx: &EncodedConfirmedTransactionWithStatusMeta) -> Result<HashMap<Pubkey, TokenBalance>> {
+    let keys = decode::account_keys(tx)?;
+    let meta = match &tx.transaction.meta {
+        Some(meta) => meta,
+        None => return Ok(HashMap::new()),
+    };
+    let parse = |balance: &UiTransactionTokenBalance| -> Result<(Pubkey, Pubkey, u8, u64)> {
+        let key = keys
+            .get(balance.account_index as usize)
+            .copied()
+            .ok_or_else(|| Error::Decode(format!("token balance index {} out of range", balance.account_index)))?;
+        let amount = balance
+            .ui_token_amount
+            .amount
+            .parse()
+            .map_err(|_| Error::Decode(format!("token balance of {} is not an integer", key)))?;
+        Ok((key, Pubkey::from_str(&balance.mint)?, balance.ui_token_amount.decimals, amount))
+    };
+
+    // accounts created in the transaction only have a post balance, closed ones only a pre balance
+    let mut balances: HashMap<Pubkey, TokenBalance> = HashMap::new();
+    if let OptionSerializer::Some(pre) = &meta.pre_token_balances {
+        for balance in pre {
+            let (key, mint, decimals, amount) = parse(balance)?;
+            balances.insert(key, TokenBalance { mint, decimals, pre: amount, post: 0 });
+        }
+    }
+    if let OptionSerializer::Some(post) = &meta.post_token_balances {
+        for balance in post {
+            let (key, mint, decimals, amount) = parse(balance)?;
+            balances
+                .entry(key)
+                .or_insert(TokenBalance { mint, decimals, pre: 0, post: 0 })
+                .post = amount;
+        }
+    }
+    Ok(balances)
+}
+
+/// What a pool took in and paid out, from its coin and pc vault balances
+struct VaultFlow {
+    direction: SwapDirection,
+    input: TokenBalance,
+    output: TokenBalance,
+    amount_in: u64,
+    amount_out: u64,
+}
+
+impl VaultFlow {
+    fn new(balances: &HashMap<Pubkey, TokenBalance>, coin_vault: &Pubkey, pc_vault: &Pubkey) -> Option<Self> {
+        let coin = *balances.get(coin_vault)?;
+        let pc = *balances.get(pc_vault)?;
+        let coin_delta = coin.post as i128 - coin.pre as i128;
+        let pc_delta = pc.post as i128 - pc.pre as i128;
+        let (direction, input, output, amount_in, amount_out) = if coin_delta > 0 && pc_delta < 0 {
+            (SwapDirection::Coin2PC, coin, pc, coin_delta, -pc_delta)
+        } else if pc_delta > 0 && coin_delta < 0 {
+            (SwapDirection::PC2Coin, pc, coin, pc_delta, -coin_delta)
+        } else {
+            return None;
+        };
+        Some(VaultFlow {
+            direction,
+            input,
+            output,
+            amount_in: amount_in as u64,
+            amount_out: amount_out as u64,
+        })
+    }
+
+    fn effective_price(&self) -> f64 {
+        let ui = |amount: u64, balance: &TokenBalance| amount as f64 / 10f64.powi(balance.decimals as i32);
+        let amount_in = ui(self.amount_in, &self.input);
+        let amount_out = ui(self.amount_out, &self.output);
+        match self.direction {
+            SwapDirection::Coin2PC => amount_out / amount_in,
+            SwapDirection::PC2Coin => amount_in / amount_out,
+        }
+    }
+}
+
+fn signature(tx: &EncodedConfirmedTransactionWithStatusMeta) -> Result<Signature> {
+    tx.transaction
+        .transaction
+        .decode()
+        .and_then(|decoded| decoded.signatures.first().copied())
+        .ok_or_else(|| Error::Decode("transaction must be fetched with a binary encoding".to_string()))
+}
+
+fn failed(tx: &EncodedConfirmedTransactionWithStatusMeta) -> bool {
+    tx.transaction.meta.as_ref().map_or(false, |meta| meta.err.is_some())
+}
+
+fn raydium_record(
+    ix: &DecodedInstruction,
+    balances: &HashMap<Pubkey, TokenBalance>,
+    pools: &PoolIndex,
+    signature: Signature,
+    slot: u64,
+) -> Option<SwapRecord> {
+    let (minimum_amount_out, maximum_amount_in) = match &ix.instruction {
+        AmmInstruction::SwapBaseIn(swap) => (Some(swap.minimum_amount_out), None),
+        AmmInstruction::SwapBaseOut(swap) => (None, Some(swap.max_amount_in)),
+        _ => return None,
+    };
+    let pool = ix.account("amm_pool")?;
+    let flow = VaultFlow::new(balances, &ix.account("amm_coin_vault")?, &ix.account("amm_pc_vault")?)?;
+    let fee = pools.get(&pool).and_then(|amm| {
+        let numerator = (flow.amount_in as u128).checked_mul(amm.fees.swap_fee_numerator as u128)?;
+        let denominator = amm.fees.swap_fee_denominator as u128;
+        if denominator == 0 {
+            return None;
+        }
+        Some(((numerator + denominator - 1) / denominator) as u64)
+    });
+    let slippage_bps = match (minimum_amount_out, maximum_amount_in) {
+        (Some(minimum), _) if minimum > 0 => {
+            Some(((flow.amount_out as i128 - minimum as i128) * BPS / minimum as i128) as i64)
+        }
+        (_, Some(maximum)) if maximum > 0 => {
+            Some(((maximum as i128 - flow.amount_in as i128) * BPS / maximum as i128) as i64)
+        }
+        _ => None,
+    };
+    Some(SwapRecord {
+        signature,
+        slot,
+        venue: Venue::Raydium,
+        pool,
+        wallet: ix.account("user_source_owner")?,
+        direction: flow.direction,
+        input_mint: flow.input.mint,
+        output_mint: flow.output.mint,
+        amount_in: flow.amount_in,
+        amount_out: flow.amount_out,
+        fee,
+        effective_price: flow.effective_price(),
+        minimum_amount_out,
+        maximum_amount_in,
+        slippage_bps,
+    })
+}
+
+/// Swaps against Raydium pools of `program_id` in `tx`. `pools` supplies the fees,
+/// pools missing from it get no fee.
+pub fn raydium_swaps(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    program_id: &Pubkey,
+    pools: &PoolIndex,
+) -> Result<Vec<SwapRecord>> {
+    if failed(tx) {
+        return Ok(vec![]);
+    }
+    let signature = signature(tx)?;
+    let balances = token_balances(tx)?;
+    let swaps: Vec<DecodedInstruction> = decode::decode_transaction(tx, program_id)?
+        .into_iter()
+        .filter(|ix| matches!(ix.instruction, AmmInstruction::SwapBaseIn(_) | AmmInstruction::SwapBaseOut(_)))
+        .collect();
+    let mut records: Vec<SwapRecord> = Vec::new();
+    for ix in swaps.iter() {
+        let pool = match ix.account("amm_pool") {
+            Some(pool) => pool,
+            None => continue,
+        };
+        // the vault balances already hold every swap against the pool
+        if records.iter().any(|seen| seen.pool == pool) {
+            continue;
+        }
+        if let Some(mut record) = raydium_record(ix, &balances, pools, signature, tx.slot) {
+            // no single instruction's limit applies to the net of several
+            if swaps.iter().filter(|other| other.account("amm_pool") == Some(pool)).count() > 1 {
+                record.minimum_amount_out = None;
+                record.maximum_amount_in = None;
+                record.slippage_bps = None;
+            }
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Swaps against the SolFi `markets` in `tx`, the fee payer is taken as the wallet
+pub fn solfi_swaps(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    markets: &HashMap<Pubkey, MarketAccount>,
+) -> Result<Vec<SwapRecord>> {
+    if failed(tx) {
+        return Ok(vec![]);
+    }
+    let signature = signature(tx)?;
+    let balances = token_balances(tx)?;
+    let wallet = *decode::account_keys(tx)?
+        .first()
+        .ok_or_else(|| Error::Decode("transaction has no accounts".to_string()))?;
+    let program_id = Pubkey::from_str(SOLFI_PROGRAM_ID).unwrap();
+
+    let mut records: Vec<SwapRecord> = Vec::new();
+    for ix in decode::resolve_instructions(tx)? {
+        if ix.program_id != program_id {
+            continue;
+        }
+        let (pool, market) = match ix.accounts.iter().find_map(|key| markets.get(key).map(|market| (*key, market))) {
+            Some(found) => found,
+            None => continue,
+        };
+        // the vault balances already hold every swap against the market
+        if records.iter().any(|record| record.pool == pool) {
+            continue;
+        }
+        if let Some(flow) = VaultFlow::new(&balances, &market.base_vault, &market.quote_vault) {
+            records.push(SwapRecord {
+                signature,
+                slot: tx.slot,
+                venue: Venue::Solfi,
+                pool,
+                wallet,
+                direction: flow.direction,
+                input_mint: flow.input.mint,
+                output_mint: flow.output.mint,
+                amount_in: flow.amount_in,
+                amount_out: flow.amount_out,
+                fee: None,
+                effective_price: flow.effective_price(),
+                minimum_amount_out: None,
+                maximum_amount_in: None,
+                slippage_bps: None,
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// Raydium and SolFi swaps of `tx`
+pub fn extract_swaps(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    raydium_program_id: &Pubkey,
+    pools: &PoolIndex,
+    markets: &HashMap<Pubkey, MarketAccount>,
+) -> Result<Vec<SwapRecord>> {
+    let mut records = raydium_swaps(tx, raydium_program_id, pools)?;
+    records.extend(solfi_swaps(tx, markets)?);
+    Ok(records)
+}
+
+/// Records grouped by wallet, each wallet's in slot order
+pub fn by_wallet(records: impl IntoIterator<Item = SwapRecord>) -> BTreeMap<Pubkey, Vec<SwapRecord>> {
+    let mut wallets: BTreeMap<Pubkey, Vec<SwapRecord>> = BTreeMap::new();
+    for record in records {
+        wallets.entry(record.wallet).or_default().push(record);
+    }
+    for records in wallets.values_mut() {
+        records.sort_by_key(|record| record.slot);
+    }
+    wallets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use solana_sdk::message::Message;
+    use crate::raydium::client::RAYDIUM_AMM_PROGRAM_ID;
+    use crate::raydium::decode::tests::{confirmed, swap};
+
+    /// `swaps` against one pool whose coin vault goes 1000 -> `coin_post` and pc vault 2000 -> `pc_post`
+    fn pool_swaps(swaps: usize, coin_post: u64, pc_post: u64) -> (Pubkey, Vec<SwapRecord>) {
+        let program_id = Pubkey::from_str(RAYDIUM_AMM_PROGRAM_ID).unwrap();
+        let (amm_pool, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = swap(&amm_pool, &owner);
+        let (coin_vault, pc_vault) = (ix.accounts[4].pubkey, ix.accounts[5].pubkey);
+        let (coin_mint, pc_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let message = Message::new(&vec![ix; swaps], Some(&owner));
+        let index = |key: &Pubkey| message.account_keys.iter().position(|k| k == key).unwrap();
+        let balance = |vault: &Pubkey, mint: &Pubkey, amount: u64| {
+            json!({
+                "accountIndex": index(vault),
+                "mint": mint.to_string(),
+                "uiTokenAmount": {
+                    "uiAmount": null,
+                    "decimals": 6,
+                    "amount": amount.to_string(),
+                    "uiAmountString": "",
+                },
+            })
+        };
+        let meta = json!({
+            "err": null,
+            "status": { "Ok": null },
+            "fee": 5000,
+            "preBalances": [],
+            "postBalances": [],
+            "preTokenBalances": [balance(&coin_vault, &coin_mint, 1000), balance(&pc_vault, &pc_mint, 2000)],
+            "postTokenBalances": [balance(&coin_vault, &coin_mint, coin_post), balance(&pc_vault, &pc_mint, pc_post)],
+        });
+        let tx = confirmed(message, meta);
+        (amm_pool, raydium_swaps(&tx, &program_id, &PoolIndex::default()).unwrap())
+    }
+
+    #[test]
+    fn one_swap_reports_its_slippage() {
+        let (pool, records) = pool_swaps(1, 2000, 1000);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.pool, pool);
+        assert_eq!(record.direction, SwapDirection::Coin2PC);
+        assert_eq!((record.amount_in, record.amount_out), (1000, 1000));
+        assert_eq!(record.minimum_amount_out, Some(990));
+        // (1000 - 990) * 10_000 / 990
+        assert_eq!(record.slippage_bps, Some(101));
+    }
+
+    #[test]
+    fn swaps_sharing_a_pool_report_the_net_without_limits() {
+        let (pool, records) = pool_swaps(2, 1100, 1810);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.pool, pool);
+        assert_eq!(record.direction, SwapDirection::Coin2PC);
+        assert_eq!((record.amount_in, record.amount_out), (100, 190));
+        assert_eq!(record.minimum_amount_out, None);
+        assert_eq!(record.maximum_amount_in, None);
+        assert_eq!(record.slippage_bps, None);
+    }
+}
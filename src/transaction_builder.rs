@@ -0,0 +1,241 @@
+//! v0 transactions compiled with the fewest lookup tables that fit them in a packet
+
+use std::collections::HashSet;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::{v0, VersionedMessage};
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::signers::Signers;
+use solana_sdk::transaction::VersionedTransaction;
+use crate::error::{Error, Result};
+use crate::lookup_table::{self, LookupTable};
+
+/// Up to this many useful tables every subset is tried, above it tables are picked greedily
+const MAX_EXACT_TABLES: usize = 10;
+
+pub struct V0TransactionBuilder {
+    payer: Pubkey,
+    instructions: Vec<Instruction>,
+    lookup_tables: Vec<LookupTable>,
+}
+
+impl V0TransactionBuilder {
+    pub fn new(payer: Pubkey) -> Self {
+        V0TransactionBuilder {
+            payer,
+            instructions: vec![],
+            lookup_tables: vec![],
+        }
+    }
+
+    pub fn instruction(mut self, instruction: Instruction) -> Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    pub fn instructions(mut self, instructions: impl IntoIterator<Item = Instruction>) -> Self {
+        self.instructions.extend(instructions);
+        self
+    }
+
+    /// Candidate tables, deactivated ones are never used
+    pub fn lookup_tables(mut self, lookup_tables: impl IntoIterator<Item = LookupTable>) -> Self {
+        self.lookup_tables.extend(lookup_tables);
+        self
+    }
+
+    /// Keys a table can supply: everything but signers and invoked programs
+    fn loadable_keys(&self) -> Vec<Pubkey> {
+        let programs: HashSet<Pubkey> = self.instructions.iter().map(|ix| ix.program_id).collect();
+        let mut seen = HashSet::new();
+        self.instructions
+            .iter()
+            .flat_map(|ix| ix.accounts.iter())
+            .filter(|meta| !meta.is_signer && meta.pubkey != self.payer)
+            .map(|meta| meta.pubkey)
+            .filter(|key| !programs.contains(key) && seen.insert(*key))
+            .collect()
+    }
+
+    fn try_compile(&self, tables: &[&LookupTable], recent_blockhash: Hash) -> Result<v0::Message> {
+        let accounts: Vec<AddressLookupTableAccount> = tables
+            .iter()
+            .map(|table| AddressLookupTableAccount {
+                key: table.key,
+                addresses: table.addresses.clone(),
+            })
+            .collect();
+        v0::Message::try_compile(&self.payer, &self.instructions, &accounts, recent_blockhash)
+            .map_err(|e| Error::Other(format!("v0 compile failed: {}", e)))
+    }
+
+    /// Compiles with the smallest set of tables that gets the signed transaction
+    /// within `PACKET_DATA_SIZE`, preferring the smaller message among sets of equal count
+    pub fn compile(&self, recent_blockhash: Hash) -> Result<v0::Message> {
+        // more than 256 static keys fails to compile, tables may still fit it
+        if let Ok(message) = self.try_compile(&[], recent_blockhash) {
+            if transaction_size(&message)? <= PACKET_DATA_SIZE {
+                return Ok(message);
+            }
+        }
+
+        let coverage = lookup_table::coverage(&self.lookup_tables, &self.loadable_keys());
+        let useful: Vec<&LookupTable> = coverage
+            .tables
+            .iter()
+            .filter_map(|covering| self.lookup_tables.iter().find(|table| table.key == covering.table))
+            .collect();
+
+        if useful.len() <= MAX_EXACT_TABLES {
+            for count in 1..=useful.len() {
+                let mut best: Option<(usize, v0::Message)> = None;
+                for subset in subsets(&useful, count) {
+                    // a table can hold a key past the index a message can reference
+                    let message = match self.try_compile(&subset, recent_blockhash) {
+                        Ok(message) => message,
+                        Err(_) => continue,
+                    };
+                    let size = transaction_size(&message)?;
+                    if size <= PACKET_DATA_SIZE && best.as_ref().map_or(true, |(best_size, _)| size < *best_size) {
+                        best = Some((size, message));
+                    }
+                }
+                if let Some((_, message)) = best {
+                    return Ok(message);
+                }
+            }
+        } else {
+            let mut selected: Vec<&LookupTable> = Vec::new();
+            for key in coverage.selected.iter() {
+                if let Some(table) = useful.iter().find(|table| table.key == *key) {
+                    selected.push(*table);
+                }
+                if let Ok(message) = self.try_compile(&selected, recent_blockhash) {
+                    if transaction_size(&message)? <= PACKET_DATA_SIZE {
+                        return Ok(message);
+                    }
+                }
+            }
+        }
+
+        let message = self.try_compile(&useful, recent_blockhash)?;
+        Err(Error::InvalidState(format!(
+            "transaction is {} bytes with every lookup table, max {}",
+            transaction_size(&message)?,
+            PACKET_DATA_SIZE
+        )))
+    }
+
+    /// Transaction with placeholder signatures, ready to be signed by the message signers
+    pub fn build_unsigned(&self, recent_blockhash: Hash) -> Result<VersionedTransaction> {
+        let message = self.compile(recent_blockhash)?;
+        Ok(VersionedTransaction {
+            signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+            message: VersionedMessage::V0(message),
+        })
+    }
+
+    pub fn build<T: Signers + ?Sized>(&self, recent_blockhash: Hash, signers: &T) -> Result<VersionedTransaction> {
+        let message = self.compile(recent_blockhash)?;
+        Ok(VersionedTransaction::try_new(VersionedMessage::V0(message), signers)?)
+    }
+}
+
+/// Serialized size of the transaction once every required signature is set
+pub fn transaction_size(message: &v0::Message) -> Result<usize> {
+    let tx = VersionedTransaction {
+        signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+        message: VersionedMessage::V0(message.clone()),
+    };
+    Ok(bincode::serialized_size(&tx)? as usize)
+}
+
+/// Every `count` sized subset of `items`, in order
+fn subsets<'a, T>(items: &[&'a T], count: usize) -> Vec<Vec<&'a T>> {
+    if count == 0 {
+        return vec![vec![]];
+    }
+    if items.len() < count {
+        return vec![];
+    }
+    let mut result = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        for mut rest in subsets(&items[index + 1..], count - 1) {
+            rest.insert(0, *item);
+            result.push(rest);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::address_lookup_table::state::LookupTableMeta;
+    use solana_sdk::instruction::AccountMeta;
+
+    fn table(addresses: Vec<Pubkey>) -> LookupTable {
+        LookupTable {
+            key: Pubkey::new_unique(),
+            meta: LookupTableMeta::default(),
+            addresses,
+        }
+    }
+
+    fn builder(keys: &[Pubkey]) -> V0TransactionBuilder {
+        let accounts = keys.iter().map(|key| AccountMeta::new_readonly(*key, false)).collect();
+        V0TransactionBuilder::new(Pubkey::new_unique())
+            .instruction(Instruction::new_with_bytes(Pubkey::new_unique(), &[], accounts))
+    }
+
+    fn used_tables(message: &v0::Message) -> HashSet<Pubkey> {
+        message.address_table_lookups.iter().map(|lookup| lookup.account_key).collect()
+    }
+
+    #[test]
+    fn small_transaction_uses_no_table() {
+        let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let message = builder(&keys)
+            .lookup_tables(vec![table(keys.clone())])
+            .compile(Hash::default())
+            .unwrap();
+        assert!(message.address_table_lookups.is_empty());
+    }
+
+    #[test]
+    fn picks_the_smallest_fitting_set_of_tables() {
+        let keys: Vec<Pubkey> = (0..64).map(|_| Pubkey::new_unique()).collect();
+        let first = table(keys[..32].to_vec());
+        let second = table(keys[32..].to_vec());
+        // adds nothing to `first` but still makes `second` fit on its own
+        let part = table(keys[..10].to_vec());
+        let expected: HashSet<Pubkey> = [first.key, second.key].into_iter().collect();
+
+        let message = builder(&keys)
+            .lookup_tables(vec![first, second, part])
+            .compile(Hash::default())
+            .unwrap();
+        assert_eq!(used_tables(&message), expected);
+        assert!(transaction_size(&message).unwrap() <= PACKET_DATA_SIZE);
+    }
+
+    #[test]
+    fn skips_tables_that_fail_to_compile() {
+        let keys: Vec<Pubkey> = (0..64).map(|_| Pubkey::new_unique()).collect();
+        // every key sits past index 255, out of reach of a message
+        let mut far_addresses: Vec<Pubkey> = (0..256).map(|_| Pubkey::new_unique()).collect();
+        far_addresses.extend_from_slice(&keys);
+        let far = table(far_addresses);
+        let near = table(keys.clone());
+        let near_key = near.key;
+
+        let message = builder(&keys)
+            .lookup_tables(vec![far, near])
+            .compile(Hash::default())
+            .unwrap();
+        assert_eq!(used_tables(&message), [near_key].into_iter().collect());
+    }
+}